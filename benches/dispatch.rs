@@ -0,0 +1,36 @@
+// Match vs function-table dispatch in `Emulator::step` on a tight arithmetic loop.
+// Run with `cargo bench --features bench`.
+use criterion::{Criterion, criterion_group, criterion_main};
+use crustzincgd::emulator::Emulator;
+use crustzincgd::neozasm::assemble;
+
+// ~100k steps: six ALU/jump instructions per pass until A wraps
+const ARITHMETIC_LOOP: &str = "\
+loop:
+add A, 3, A
+xor A, B, B
+shl B, 1, C
+sub C, A, D
+inc A
+jmne A, 0, loop
+";
+
+fn dispatch(c: &mut Criterion) {
+    let program = assemble(ARITHMETIC_LOOP).unwrap();
+    let mut group = c.benchmark_group("dispatch");
+    for (name, use_table) in [("match", false), ("table", true)] {
+        group.bench_function(name, |b| {
+            let mut emu = Emulator::new();
+            emu.set_table_dispatch(use_table);
+            b.iter(|| {
+                emu.reset();
+                emu.load_program(&program).unwrap();
+                emu.run(u64::MAX)
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -1,30 +1,125 @@
+// Signed mode (bit 0 of O, see `is_signed`) treats register values as i16 for
+// the add/inc overflow threshold, the jml/jmle/jmb/jmbe jumps, cmp, set* and
+// shr, which then shifts arithmetically. Everything else is the same in both
+// modes: values wrap at 16 bits and mul/div/mod stay unsigned.
 
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
 
-const MEM_SIZE: usize = 65536;
-const NUM_REGS: usize = 12;
+use crate::neozasm::{disassemble_instruction, mnemonic};
 
-const REG_A: usize = 0;
-const REG_B: usize = 1;
-const REG_C: usize = 2;
-const REG_D: usize = 3;
-const REG_IP: usize = 4;
-const REG_SS: usize = 5;
-const REG_SO: usize = 6;
-const REG_MS: usize = 7;
-const REG_MO: usize = 8;
-const REG_I: usize = 9;
-const REG_O: usize = 10;
-const REG_ST: usize = 11;
+// default (and largest useful) RAM size: addresses are 16-bit
+pub const MEM_SIZE: usize = 65536;
+pub const NUM_REGS: usize = 12;
+
+pub const REG_A: usize = 0;
+pub const REG_B: usize = 1;
+pub const REG_C: usize = 2;
+pub const REG_D: usize = 3;
+pub const REG_IP: usize = 4;
+pub const REG_SS: usize = 5;
+pub const REG_SO: usize = 6;
+pub const REG_MS: usize = 7;
+pub const REG_MO: usize = 8;
+pub const REG_I: usize = 9;
+pub const REG_O: usize = 10;
+pub const REG_ST: usize = 11;
+
+// Result bits `cmp` leaves in REG_ST for the flag jumps; exactly one is set.
+pub const ST_ZERO: u16 = 1 << 0;
+pub const ST_LESS: u16 = 1 << 1;
+pub const ST_GREATER: u16 = 1 << 2;
+
+pub const REGISTER_NAMES: [&str; NUM_REGS] = [
+    "A", "B", "C", "D", "IP", "SS", "SO", "MS", "MO", "I", "O", "ST",
+];
+
+pub fn register_index(name: &str) -> Option<usize> {
+    REGISTER_NAMES.iter().position(|&reg| reg == name)
+}
+
+// Flag bits in REG_O; bit 0 mirrors `is_signed`.
+const O_OVERFLOW: u16 = 1 << 1;
+const O_DIV_ERROR: u16 = 1 << 2;
+const O_BORROW: u16 = 1 << 3;
+// bit rotated out by rcl/rcr and back in on the next one
+const O_CARRY: u16 = 1 << 4;
+// set when a push hits the stack limit or a pop finds the stack empty
+const O_STACK_FAULT: u16 = 1 << 5;
+
+const DEFAULT_RNG_SEED: u32 = 0x2545F491;
+const DEFAULT_TRACE_CAPACITY: usize = 64;
+// steps between clock reads in `run_with_timeout`
+const TIMEOUT_CHECK_INTERVAL: u64 = 4096;
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum StepResult {
     Continue,
     Halt,
+    // the instruction ran and IP now sits on a breakpoint
+    Breakpoint,
+}
+
+// Why `run_with_timeout` stopped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunOutcome {
+    Halt,
+    Breakpoint,
+    StepLimit,
+    Timeout,
+}
+
+impl RunOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunOutcome::Halt => "halt",
+            RunOutcome::Breakpoint => "breakpoint",
+            RunOutcome::StepLimit => "step_limit",
+            RunOutcome::Timeout => "timeout",
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HaltReason {
+    Instruction,
+    OutOfBounds,
+    UninitializedRead,
+    DebugBreak,
+    StrictViolation,
+    StackOverflow,
+    StackUnderflow,
+}
+
+impl HaltReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HaltReason::Instruction => "halt",
+            HaltReason::OutOfBounds => "out_of_bounds",
+            HaltReason::UninitializedRead => "uninitialized_read",
+            HaltReason::DebugBreak => "debug_break",
+            HaltReason::StrictViolation => "strict_violation",
+            HaltReason::StackOverflow => "stack_overflow",
+            HaltReason::StackUnderflow => "stack_underflow",
+        }
+    }
+}
+
+// In declaration order, so `reason as u8` indexes it (used by save-states).
+const HALT_REASONS: [HaltReason; 7] = [
+    HaltReason::Instruction,
+    HaltReason::OutOfBounds,
+    HaltReason::UninitializedRead,
+    HaltReason::DebugBreak,
+    HaltReason::StrictViolation,
+    HaltReason::StackOverflow,
+    HaltReason::StackUnderflow,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(u16)]
-enum Opcode {
+pub enum Opcode {
     Mov = 0,
     Add = 1,
     Sub = 2,
@@ -47,8 +142,46 @@ enum Opcode {
     Halt = 19,
     Shl = 20,
     Shr = 21,
+    Loada = 22,
+    Rand = 23,
+    Div = 24,
+    Mod = 25,
+    Mac = 26,
+    Dbgbreak = 27,
+    Peek = 28,
+    Andn = 29,
+    Ptrdiff = 30,
+    Setlt = 31,
+    Sete = 32,
+    Setgt = 33,
+    Rcl = 34,
+    Rcr = 35,
+    Cmp = 36,
+    Jz = 37,
+    Jnz = 38,
+    Jg = 39,
+    Jl = 40,
+    Jge = 41,
+    Jle = 42,
+    Call = 43,
+    Ret = 44,
+    Inc = 45,
+    Dec = 46,
+    Rol = 47,
+    Ror = 48,
+    Nop = 49,
+    Loadb = 50,
+    Saveb = 51,
+    Test = 52,
+    Bset = 53,
+    Bclr = 54,
+    Btst = 55,
+    Copy = 56,
+    Fill = 57,
 }
 
+pub const NUM_OPCODES: usize = Opcode::Fill as usize + 1;
+
 impl From<u16> for Opcode {
     fn from(op: u16) -> Self {
         match op {
@@ -74,23 +207,177 @@ impl From<u16> for Opcode {
             19 => Opcode::Halt,
             20 => Opcode::Shl,
             21 => Opcode::Shr,
+            22 => Opcode::Loada,
+            23 => Opcode::Rand,
+            24 => Opcode::Div,
+            25 => Opcode::Mod,
+            26 => Opcode::Mac,
+            27 => Opcode::Dbgbreak,
+            28 => Opcode::Peek,
+            29 => Opcode::Andn,
+            30 => Opcode::Ptrdiff,
+            31 => Opcode::Setlt,
+            32 => Opcode::Sete,
+            33 => Opcode::Setgt,
+            34 => Opcode::Rcl,
+            35 => Opcode::Rcr,
+            36 => Opcode::Cmp,
+            37 => Opcode::Jz,
+            38 => Opcode::Jnz,
+            39 => Opcode::Jg,
+            40 => Opcode::Jl,
+            41 => Opcode::Jge,
+            42 => Opcode::Jle,
+            43 => Opcode::Call,
+            44 => Opcode::Ret,
+            45 => Opcode::Inc,
+            46 => Opcode::Dec,
+            47 => Opcode::Rol,
+            48 => Opcode::Ror,
+            49 => Opcode::Nop,
+            50 => Opcode::Loadb,
+            51 => Opcode::Saveb,
+            52 => Opcode::Test,
+            53 => Opcode::Bset,
+            54 => Opcode::Bclr,
+            55 => Opcode::Btst,
+            56 => Opcode::Copy,
+            57 => Opcode::Fill,
             _ => Opcode::Halt,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodedInstruction {
+    pub ip: u16,
+    pub opcode: Opcode,
+    // immediate flag bits for a/b/c (bit 0 = a)
+    pub flags: u16,
+    pub a: u16,
+    pub b: u16,
+    pub c: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadError {
+    // the program would end at byte `end`, past the `capacity` bytes of RAM
+    Overflow { end: usize, capacity: usize },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MemoryFootprint {
+    // first byte past the loaded program
+    pub code_end: usize,
+    // highest address written by push / save since reset
+    pub stack_peak: Option<usize>,
+    pub data_peak: Option<usize>,
+}
+
+// Cloning copies all VM state: RAM plus its written bitmap (72 KiB at the
+// default size), and the trace, breakpoints and decode cache. Cheap enough
+// per frame or per user action, not per step.
+#[derive(Clone)]
 pub struct Emulator {
     regs: [u16; NUM_REGS],
-    ram: [u8; MEM_SIZE],
+    // on the heap so constructing or moving an Emulator doesn't copy 64 KiB
+    // on the stack; its length is the instance's memory size
+    ram: Box<[u8]>,
     is_signed: bool,
+    rng_seed: u32,
+    rng_state: u32,
+    // one bit per RAM byte, set on every write
+    written: Vec<u64>,
+    trap_uninitialized: bool,
+    // why the last halt happened and the IP of the instruction that caused it
+    halt_reason: Option<(HaltReason, u16)>,
+    debug_mode: bool,
+    debug_snapshot: Option<[u16; NUM_REGS]>,
+    footprint: MemoryFootprint,
+    // instruction indices (IP units)
+    breakpoints: HashSet<u16>,
+    // last executed (ip, opcode) pairs, oldest first
+    trace: VecDeque<(u16, u16)>,
+    trace_enabled: bool,
+    trace_capacity: usize,
+    // executions per opcode since reset; None while profiling is off
+    opcode_counts: Option<Box<[u64; NUM_OPCODES]>>,
+    // steps since reset
+    cycles: u64,
+    // per-IP decoded instructions, filled only by `precompile`; empty otherwise
+    decoded: Vec<Option<DecodedInstruction>>,
+    // instructions decoded from RAM, by `step` or `precompile`
+    decodes: u64,
+    // host callback for every halt, with the reason and `halt_ip`
+    halt_hook: HookSlot,
+    // development aid: check VM invariants around every step
+    strict: bool,
+    // what the last StrictViolation halt tripped over
+    strict_violation: Option<String>,
+    // bench builds can route `step` through the DISPATCH table
+    #[cfg(feature = "bench")]
+    table_dispatch: bool,
+    // byte range [start, end) whose writes are queued instead of stored
+    mmio: Option<(usize, usize)>,
+    // (addr, value) per write into `mmio`, oldest first
+    mmio_events: Vec<(u16, u16)>,
+    // host input waiting to be latched into I
+    input: VecDeque<u16>,
+    // largest SO a push may leave behind; None = the room between SS and MS
+    stack_limit: Option<u16>,
+}
+
+pub type HaltHook = Box<dyn FnMut(HaltReason, u16) + Send>;
+
+// A boxed closure can't be copied, and the hook is host wiring rather than VM
+// state, so a cloned emulator starts without one.
+#[derive(Default)]
+struct HookSlot(Option<HaltHook>);
+
+impl Clone for HookSlot {
+    fn clone(&self) -> Self {
+        HookSlot(None)
+    }
 }
 
 impl Default for Emulator {
     fn default() -> Self {
+        Emulator::blank(MEM_SIZE)
+    }
+}
+
+impl Emulator {
+    // Un-reset emulator with `bytes` of RAM.
+    fn blank(bytes: usize) -> Self {
         Emulator {
             regs: [0; NUM_REGS],
-            ram: [0; MEM_SIZE],
+            ram: vec![0; bytes].into_boxed_slice(),
             is_signed: false,
+            rng_seed: DEFAULT_RNG_SEED,
+            rng_state: DEFAULT_RNG_SEED,
+            written: vec![0; bytes.div_ceil(64)],
+            trap_uninitialized: false,
+            halt_reason: None,
+            debug_mode: false,
+            debug_snapshot: None,
+            footprint: MemoryFootprint::default(),
+            breakpoints: HashSet::new(),
+            trace: VecDeque::new(),
+            trace_enabled: false,
+            trace_capacity: DEFAULT_TRACE_CAPACITY,
+            opcode_counts: None,
+            cycles: 0,
+            decoded: Vec::new(),
+            decodes: 0,
+            halt_hook: HookSlot::default(),
+            strict: false,
+            strict_violation: None,
+            #[cfg(feature = "bench")]
+            table_dispatch: false,
+            mmio: None,
+            mmio_events: Vec::new(),
+            input: VecDeque::new(),
+            stack_limit: None,
         }
     }
 }
@@ -102,21 +389,78 @@ impl Emulator {
         emu
     }
 
+    // An emulator with `bytes` of RAM (capped at the 64 KiB a 16-bit address
+    // can reach). Every access past the end is refused like one past 64 KiB:
+    // reads give 0, writes are dropped and IP out of range halts.
+    pub fn with_memory_size(bytes: usize) -> Self {
+        let mut emu = Emulator::blank(bytes.min(MEM_SIZE));
+        emu.reset();
+        emu
+    }
+
+    pub fn memory_size(&self) -> usize {
+        self.ram.len()
+    }
+
     pub fn reset(&mut self) {
         self.regs = [0; NUM_REGS];
-        self.ram = [0; MEM_SIZE];
-        self.regs[REG_SS] = 0x4000;
-        self.regs[REG_MS] = 0x8000;
+        self.ram.fill(0);
+        // stack from 1/4 and data from 1/2 of RAM: 0x4000 / 0x8000 at 64 KiB
+        self.regs[REG_SS] = (self.ram.len() / 4) as u16;
+        self.regs[REG_MS] = (self.ram.len() / 2) as u16;
         self.regs[REG_MO] = 0;
         self.regs[REG_I] = 0;
         self.regs[REG_ST] = 0;
         self.is_signed = false;
+        self.rng_state = self.rng_seed;
+        self.written.fill(0);
+        self.halt_reason = None;
+        self.debug_snapshot = None;
+        self.footprint = MemoryFootprint::default();
+        self.trace.clear();
+        if let Some(counts) = &mut self.opcode_counts {
+            counts.fill(0);
+        }
+        self.cycles = 0;
+        self.decoded.clear();
+        self.decodes = 0;
+        self.strict_violation = None;
+        self.mmio_events.clear();
+        self.input.clear();
+    }
+
+    // Zeroes each register whose bit is set in `mask` (bit n = register n),
+    // leaving RAM and the other registers alone.
+    pub fn reset_registers(&mut self, mask: u16) {
+        for idx in 0..NUM_REGS {
+            if mask & (1 << idx) != 0 {
+                self.write_reg(idx as u16, 0);
+            }
+        }
+    }
+
+    // The seed survives `reset()` so a reseeded run replays the same sequence.
+    pub fn seed_rng(&mut self, seed: u32) {
+        // xorshift never leaves the all-zero state
+        self.rng_seed = if seed == 0 { DEFAULT_RNG_SEED } else { seed };
+        self.rng_state = self.rng_seed;
+    }
+
+    fn next_rand(&mut self) -> u16 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        (x >> 16) as u16
     }
 
     fn read_reg(&self, idx: u16) -> u16 {
         self.regs[idx as usize]
     }
 
+    // Every register write goes through here, so `is_signed` tracks bit 0 of O
+    // whether O is written by mov/pop/any ALU destination or by a flag update.
     fn write_reg(&mut self, idx: u16, val: u16) {
         self.regs[idx as usize] = val;
         if idx as usize == REG_O {
@@ -124,8 +468,43 @@ impl Emulator {
         }
     }
 
+    pub fn get_register(&self, idx: usize) -> u16 {
+        self.regs[idx]
+    }
+
+    pub fn get_ip(&self) -> u16 {
+        self.regs[REG_IP]
+    }
+
+    // In instruction units, like get_ip: the next step runs byte `ip * 8`.
+    pub fn set_ip(&mut self, ip: u16) {
+        self.write_reg(REG_IP as u16, ip);
+    }
+
+    pub fn set_register(&mut self, idx: usize, val: u16) {
+        self.write_reg(idx as u16, val);
+    }
+
+    // Sets I directly; queued input is left alone.
+    pub fn set_input(&mut self, value: u16) {
+        self.write_reg(REG_I as u16, value);
+    }
+
+    // Queues a value for I, so several inputs between frames aren't lost. Each
+    // instruction that names I as an operand first latches the next queued
+    // value into it. Reading I never clears it: with nothing queued it keeps
+    // its last value.
+    pub fn push_input(&mut self, value: u16) {
+        self.input.push_back(value);
+    }
+
+    fn set_flag(&mut self, mask: u16, on: bool) {
+        let o = self.regs[REG_O];
+        self.write_reg(REG_O as u16, if on { o | mask } else { o & !mask });
+    }
+
     fn read_mem_u16(&self, addr: usize) -> u16 {
-        if addr + 1 >= MEM_SIZE {
+        if addr + 1 >= self.ram.len() {
             return 0;
         }
         let lo = self.ram[addr] as u16;
@@ -134,24 +513,354 @@ impl Emulator {
     }
 
     fn write_mem_u16(&mut self, addr: usize, val: u16) {
-        if addr + 1 >= MEM_SIZE {
+        if addr + 1 >= self.ram.len() {
+            return;
+        }
+        if let Some((start, end)) = self.mmio
+            && addr < end
+            && addr + 2 > start
+        {
+            self.mmio_events.push((addr as u16, val));
             return;
         }
         self.ram[addr] = (val & 0xFF) as u8;
         self.ram[addr + 1] = (val >> 8) as u8;
+        self.mark_written(addr);
+        self.mark_written(addr + 1);
+        self.invalidate_decoded(addr, addr + 2);
+    }
+
+    // Byte store with the same bounds, MMIO and bookkeeping as write_mem_u16.
+    fn write_mem_u8(&mut self, addr: usize, val: u8) {
+        if addr >= self.ram.len() {
+            return;
+        }
+        if let Some((start, end)) = self.mmio
+            && (start..end).contains(&addr)
+        {
+            self.mmio_events.push((addr as u16, val as u16));
+            return;
+        }
+        self.ram[addr] = val;
+        self.mark_written(addr);
+        self.invalidate_decoded(addr, addr + 1);
+    }
+
+    fn mark_written(&mut self, addr: usize) {
+        self.written[addr / 64] |= 1 << (addr % 64);
+    }
+
+    fn is_written(&self, addr: usize) -> bool {
+        self.written[addr / 64] & (1 << (addr % 64)) != 0
+    }
+
+    // Only meaningful with the trap enabled; out-of-range reads already yield 0.
+    fn is_uninitialized_read(&self, addr: usize) -> bool {
+        self.trap_uninitialized
+            && addr + 1 < self.ram.len()
+            && !(self.is_written(addr) && self.is_written(addr + 1))
+    }
+
+    // Word writes overlapping bytes [start, start + len) are queued for
+    // `drain_mmio_events` and leave RAM untouched; reads still see RAM.
+    // A zero `len` removes the window. Survives `reset()`.
+    pub fn set_mmio_range(&mut self, start: u16, len: u16) {
+        let start = start as usize;
+        self.mmio = (len > 0).then_some((start, (start + len as usize).min(self.ram.len())));
+    }
+
+    pub fn drain_mmio_events(&mut self) -> Vec<(u16, u16)> {
+        std::mem::take(&mut self.mmio_events)
+    }
+
+    pub fn set_trap_uninitialized(&mut self, enabled: bool) {
+        self.trap_uninitialized = enabled;
+    }
+
+    pub fn halt_reason(&self) -> Option<HaltReason> {
+        self.halt_reason.map(|(reason, _)| reason)
+    }
+
+    // IP of the instruction that halted (for out_of_bounds, the IP that
+    // couldn't be fetched). After a `halt` instruction, IP itself is already
+    // one past this.
+    pub fn halt_ip(&self) -> Option<u16> {
+        self.halt_reason.map(|(_, ip)| ip)
+    }
+
+    #[cfg(feature = "bench")]
+    pub fn set_table_dispatch(&mut self, enabled: bool) {
+        self.table_dispatch = enabled;
+    }
+
+    // Off by default. When on, `step` halts with StrictViolation instead of
+    // panicking on a bad register index or carrying on with IP past memory or
+    // SS+SO outside the stack (SS up to MS).
+    pub fn set_strict(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    pub fn strict_violation(&self) -> Option<&str> {
+        self.strict_violation.as_deref()
+    }
+
+    fn strict_halt(&mut self, message: String) -> StepResult {
+        self.strict_violation = Some(message);
+        self.halt(HaltReason::StrictViolation)
+    }
+
+    // Operands with their immediate flag clear are read as registers.
+    fn bad_register_operand(&self, flags: u16, slots: [u16; 3]) -> Option<String> {
+        (0..3)
+            .filter(|&bit| (flags >> bit) & 1 == 0)
+            .map(|bit| slots[bit as usize] & 0xFFF)
+            .find(|&idx| idx as usize >= NUM_REGS)
+            .map(|idx| format!("register index {} out of range", idx))
+    }
+
+    fn broken_invariant(&self) -> Option<String> {
+        let ip = self.regs[REG_IP];
+        if ip as usize * 8 + 8 > self.ram.len() {
+            return Some(format!("IP {} is past the end of memory", ip));
+        }
+        let (ss, so, ms) = (self.regs[REG_SS], self.regs[REG_SO], self.regs[REG_MS]);
+        let limit = if ms > ss { ms as usize } else { self.ram.len() };
+        if ss as usize + so as usize > limit {
+            return Some(format!(
+                "SO {:#06X} puts the stack top outside {:#06X}..{:#06X}",
+                so, ss, limit
+            ));
+        }
+        None
+    }
+
+    // With debug mode off, `dbgbreak` is a no-op.
+    pub fn set_debug_mode(&mut self, enabled: bool) {
+        self.debug_mode = enabled;
+    }
+
+    // Registers as they were when the last `dbgbreak` fired.
+    pub fn debug_snapshot(&self) -> Option<[u16; NUM_REGS]> {
+        self.debug_snapshot
+    }
+
+    pub fn add_breakpoint(&mut self, ip: u16) {
+        self.breakpoints.insert(ip);
+    }
+
+    pub fn remove_breakpoint(&mut self, ip: u16) {
+        self.breakpoints.remove(&ip);
     }
 
-    pub fn load_program(&mut self, program: &[u16]) {
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn set_trace_enabled(&mut self, enabled: bool) {
+        self.trace_enabled = enabled;
+    }
+
+    pub fn set_trace_capacity(&mut self, capacity: usize) {
+        self.trace_capacity = capacity;
+        while self.trace.len() > capacity {
+            self.trace.pop_front();
+        }
+    }
+
+    // Turning profiling off drops the counts gathered so far.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        if !enabled {
+            self.opcode_counts = None;
+        } else if self.opcode_counts.is_none() {
+            self.opcode_counts = Some(Box::new([0; NUM_OPCODES]));
+        }
+    }
+
+    // (mnemonic, count) for every opcode executed while profiling, in opcode order.
+    pub fn get_opcode_counts(&self) -> Vec<(String, u64)> {
+        let Some(counts) = &self.opcode_counts else {
+            return Vec::new();
+        };
+        counts
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(op, &count)| (mnemonic(op as u16).unwrap_or("???").to_string(), count))
+            .collect()
+    }
+
+    pub fn get_trace(&self) -> Vec<(u16, u16)> {
+        self.trace.iter().copied().collect()
+    }
+
+    fn record_trace(&mut self, ip: u16, opcode: u16) {
+        if self.trace_capacity == 0 {
+            return;
+        }
+        if self.trace.len() == self.trace_capacity {
+            self.trace.pop_front();
+        }
+        self.trace.push_back((ip, opcode));
+    }
+
+    // Records the reason; `step` adds the IP and calls the hook, since IP may
+    // or may not have advanced past the instruction by the time this runs.
+    fn halt(&mut self, reason: HaltReason) -> StepResult {
+        self.halt_reason = Some((reason, 0));
+        StepResult::Halt
+    }
+
+    // Independent copy of the VM for speculative execution or undo; see the
+    // note on `Emulator` for what that costs. The halt hook isn't carried over.
+    pub fn fork(&self) -> Emulator {
+        self.clone()
+    }
+
+    // Survives `reset()`. Must be Send so the emulator can move to a worker thread.
+    pub fn set_halt_hook(&mut self, hook: HaltHook) {
+        self.halt_hook.0 = Some(hook);
+    }
+
+    pub fn clear_halt_hook(&mut self) {
+        self.halt_hook.0 = None;
+    }
+
+    // Loads at address 0, or loads nothing if the program doesn't fit.
+    pub fn load_program(&mut self, program: &[u16]) -> Result<(), LoadError> {
+        self.load_program_at(program, 0)
+    }
+
+    // Loads at byte `word_offset * 2`, or loads nothing if the program doesn't fit.
+    // Instructions are 4 words, so code loaded at a multiple of 4 starts at IP
+    // `word_offset / 4`.
+    pub fn load_program_at(
+        &mut self,
+        program: &[u16],
+        word_offset: usize,
+    ) -> Result<(), LoadError> {
+        let end = word_offset.saturating_add(program.len()).saturating_mul(2);
+        if end > self.ram.len() {
+            return Err(LoadError::Overflow {
+                end,
+                capacity: self.ram.len(),
+            });
+        }
+        self.load_program_at_lossy(program, word_offset);
+        Ok(())
+    }
+
+    // Like `load_program_at`, but silently drops the words that fall past RAM.
+    pub fn load_program_at_lossy(&mut self, program: &[u16], word_offset: usize) {
         for (i, word) in program.iter().enumerate() {
-            self.write_mem_u16(i * 2, *word);
+            self.write_mem_u16(word_offset.saturating_add(i).saturating_mul(2), *word);
+        }
+        self.footprint.code_end = word_offset
+            .saturating_add(program.len())
+            .saturating_mul(2)
+            .min(self.ram.len());
+    }
+
+    pub fn ram_slice(&self) -> &[u8] {
+        &self.ram[..]
+    }
+
+    // Host-side write, clamped to RAM. Counts as initialization for the
+    // uninitialized-read trap.
+    pub fn write_ram(&mut self, addr: usize, bytes: &[u8]) {
+        let start = addr.min(self.ram.len());
+        let end = start.saturating_add(bytes.len()).min(self.ram.len());
+        self.ram[start..end].copy_from_slice(&bytes[..end - start]);
+        for a in start..end {
+            self.mark_written(a);
+        }
+        self.invalidate_decoded(start, end);
+    }
+
+    pub fn memory_footprint(&self) -> MemoryFootprint {
+        self.footprint
+    }
+
+    // Mnemonic form of the 8-byte instruction starting at byte `addr`.
+    pub fn disassemble_at(&self, addr: usize) -> String {
+        let words = [0, 2, 4, 6].map(|offset| self.read_mem_u16(addr + offset));
+        disassemble_instruction(words, None)
+    }
+
+    // `disassemble_at` over `count` instructions from IP `start_instr`, one
+    // "IP: text" line each, ending early after a halt or at the end of RAM.
+    pub fn disassemble_program(&self, start_instr: u16, count: u16) -> String {
+        let mut lines = Vec::new();
+        for ip in (start_instr as usize..).take(count as usize) {
+            if ip * 8 >= self.ram.len() {
+                break;
+            }
+            lines.push(format!("{:04X}: {}", ip, self.disassemble_at(ip * 8)));
+            if self.decode(ip as u16).opcode == Opcode::Halt {
+                break;
+            }
+        }
+        lines.join("\n")
+    }
+
+    // Decodes IPs 0..count straight from RAM; nothing is executed.
+    pub fn instructions(&self, count: usize) -> impl Iterator<Item = DecodedInstruction> + '_ {
+        (0..count.min(self.ram.len() / 8)).map(|ip| self.decode(ip as u16))
+    }
+
+    fn decode(&self, ip: u16) -> DecodedInstruction {
+        let addr = ip as usize * 8;
+        let [header, a, b, c] = [0, 2, 4, 6].map(|offset| self.read_mem_u16(addr + offset));
+        DecodedInstruction {
+            ip,
+            opcode: Opcode::from(header & 0x1FFF),
+            flags: header >> 13,
+            a,
+            b,
+            c,
         }
     }
 
+    fn fetch(&mut self, ip: u16) -> DecodedInstruction {
+        if let Some(Some(instr)) = self.decoded.get(ip as usize) {
+            return *instr;
+        }
+        self.decodes += 1;
+        self.decode(ip)
+    }
+
+    // Decodes the loaded program (up to the memory footprint's `code_end`) once,
+    // so `step` stops re-reading it from RAM. Any write into a cached slot, e.g.
+    // self-modifying code, drops that slot back to live decoding; `reset` and
+    // `restore` drop the whole cache.
+    pub fn precompile(&mut self) {
+        let slots = self.footprint.code_end.div_ceil(8).min(self.ram.len() / 8);
+        self.decoded = vec![None; self.ram.len() / 8];
+        for ip in 0..slots {
+            self.decodes += 1;
+            self.decoded[ip] = Some(self.decode(ip as u16));
+        }
+    }
+
+    pub fn decode_count(&self) -> u64 {
+        self.decodes
+    }
+
+    fn invalidate_decoded(&mut self, start: usize, end: usize) {
+        if self.decoded.is_empty() || start >= end {
+            return;
+        }
+        for slot in &mut self.decoded[start / 8..=(end - 1) / 8] {
+            *slot = None;
+        }
+    }
+
+    // An immediate operand is the word itself, so any 16-bit value (0xF00F,
+    // -1 as 0xFFFF) can be encoded. A register operand is an index in the low
+    // 12 bits plus a 4-bit offset in the top nibble; offsets above 8 count
+    // down from 16.
     pub fn r_i(&self, f: u16, param: u16, bit: u16) -> u16 {
         if (f >> bit) & 1 != 0 {
-            let offset = (param >> 12) & 0xF;
-            let value = param & 0x0FFF;
-            value.wrapping_add(offset)
+            param
         } else {
             let reg_idx = param & 0x0FFF;
             let offset = (param >> 12) & 0xF;
@@ -164,26 +873,135 @@ impl Emulator {
         }
     }
 
-    pub fn step(&mut self) -> StepResult {
+    // Ordering used by cmp, set* and the two-operand jumps.
+    fn compare(&self, va: u16, vb: u16) -> Ordering {
+        if self.is_signed {
+            (va as i16).cmp(&(vb as i16))
+        } else {
+            va.cmp(&vb)
+        }
+    }
+
+    // Where a jump would send IP, or None if `op` doesn't jump (or isn't taken).
+    fn branch_target(&self, op: Opcode, va: u16, vb: u16, vc: u16) -> Option<u16> {
+        let st = self.regs[REG_ST];
+        let ord = self.compare(va, vb);
+        let taken = match op {
+            Opcode::Jmp | Opcode::Call => true,
+            Opcode::Jml => ord.is_lt(),
+            Opcode::Jmle => ord.is_le(),
+            Opcode::Jmb => ord.is_gt(),
+            Opcode::Jmbe => ord.is_ge(),
+            Opcode::Jme => va == vb,
+            Opcode::Jmne => va != vb,
+            Opcode::Jz => st & ST_ZERO != 0,
+            Opcode::Jnz => st & ST_ZERO == 0,
+            Opcode::Jg => st & ST_GREATER != 0,
+            Opcode::Jl => st & ST_LESS != 0,
+            Opcode::Jge => st & (ST_GREATER | ST_ZERO) != 0,
+            Opcode::Jle => st & (ST_LESS | ST_ZERO) != 0,
+            _ => return None,
+        };
+        taken.then_some(vc)
+    }
+
+    // Decodes the instruction at IP and returns the IP `step()` would leave behind,
+    // without executing anything.
+    pub fn predict_next_ip(&self) -> u16 {
         let ip = self.read_reg(REG_IP as u16);
         let addr = ip as usize * 8;
-        if addr + 6 >= MEM_SIZE {
-            return StepResult::Halt;
+        if addr + 8 > self.ram.len() {
+            return ip;
         }
 
         let instr = self.read_mem_u16(addr);
         let f = (instr >> 13) & 0x7;
-        let opcode = instr & 0x1FFF;
-        let a = self.read_mem_u16(addr + 2);
-        let b = self.read_mem_u16(addr + 4);
-        let c = self.read_mem_u16(addr + 6);
+        let op = Opcode::from(instr & 0x1FFF);
+        let operand = |offset: usize, bit: u16| {
+            let param = self.read_mem_u16(addr + offset);
+            let val = self.r_i(f, param, bit);
+            // step() resolves operands after it has already advanced IP
+            if (f >> bit) & 1 == 0 && (param & 0x0FFF) as usize == REG_IP {
+                val.wrapping_add(1)
+            } else {
+                val
+            }
+        };
+        let (va, vb, vc) = (operand(2, 0), operand(4, 1), operand(6, 2));
 
-        self.write_reg(REG_IP as u16, ip.wrapping_add(1));
+        if op == Opcode::Ret {
+            let top = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]);
+            return self.read_mem_u16(top.wrapping_sub(2) as usize);
+        }
+        self.branch_target(op, va, vb, vc)
+            .unwrap_or(ip.wrapping_add(1))
+    }
+
+    // Caps SO (in bytes) for push and call; survives `reset()`.
+    pub fn set_stack_limit(&mut self, limit: u16) {
+        self.stack_limit = Some(limit);
+    }
+
+    // Back to the default: the stack may grow from SS up to MS, or to the end
+    // of RAM when MS isn't above SS.
+    pub fn clear_stack_limit(&mut self) {
+        self.stack_limit = None;
+    }
+
+    // Words from SS up to SS+SO, bottom of the stack first; empty when SO is 0.
+    pub fn stack_contents(&self) -> Vec<u16> {
+        let ss = self.regs[REG_SS] as usize;
+        (0..self.regs[REG_SO] as usize / 2)
+            .map(|i| self.read_mem_u16(ss + i * 2))
+            .collect()
+    }
 
+    fn stack_capacity(&self) -> usize {
+        if let Some(limit) = self.stack_limit {
+            return limit as usize;
+        }
+        let (ss, ms) = (self.regs[REG_SS] as usize, self.regs[REG_MS] as usize);
+        if ms > ss {
+            ms - ss
+        } else {
+            self.ram.len().saturating_sub(ss)
+        }
+    }
+
+    // A push past the stack limit is refused with the O stack-fault bit set.
+    fn push_word(&mut self, val: u16) -> Result<(), HaltReason> {
+        if self.regs[REG_SO] as usize + 2 > self.stack_capacity() {
+            self.set_flag(O_STACK_FAULT, true);
+            return Err(HaltReason::StackOverflow);
+        }
+        let addr = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]) as usize;
+        self.write_mem_u16(addr, val);
+        self.footprint.stack_peak = self.footprint.stack_peak.max(Some(addr));
+        self.regs[REG_SO] = self.regs[REG_SO].wrapping_add(2);
+        Ok(())
+    }
+
+    // A pop with SO below one word is refused with the O stack-fault bit set;
+    // a never-written word fails too when uninitialized reads trap.
+    fn pop_word(&mut self) -> Result<u16, HaltReason> {
+        if self.regs[REG_SO] < 2 {
+            self.set_flag(O_STACK_FAULT, true);
+            return Err(HaltReason::StackUnderflow);
+        }
+        self.regs[REG_SO] = self.regs[REG_SO].wrapping_sub(2);
+        let addr = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]) as usize;
+        if self.is_uninitialized_read(addr) {
+            return Err(HaltReason::UninitializedRead);
+        }
+        Ok(self.read_mem_u16(addr))
+    }
+
+    // One instruction's effect; IP already points past it.
+    #[inline(always)]
+    fn execute(&mut self, op: Opcode, f: u16, a: u16, b: u16, c: u16) -> StepResult {
         let va = self.r_i(f, a, 0);
         let vb = self.r_i(f, b, 1);
         let vc = self.r_i(f, c, 2);
-        let op = Opcode::from(opcode);
 
         match op {
             Opcode::Mov => {
@@ -194,33 +1012,100 @@ impl Emulator {
                 let target_reg = c & 0xFFF;
                 let res = va as u32 + vb as u32;
                 let max = if self.is_signed { 32767 } else { 65535 };
-                if res > max {
-                    self.write_reg(target_reg, 0);
-                    self.write_reg(REG_O as u16, self.regs[REG_O] | 2);
-                } else {
-                    self.write_reg(target_reg, res as u16);
-                    self.write_reg(REG_O as u16, self.regs[REG_O] & !2);
-                }
+                // keep the low 16 bits so carries can chain across words
+                self.write_reg(target_reg, res as u16);
+                self.set_flag(O_OVERFLOW, res > max);
             }
             Opcode::Sub => {
                 let target_reg = c & 0xFFF;
                 let res = va.wrapping_sub(vb);
                 self.write_reg(target_reg, res);
+                self.set_flag(O_BORROW, vb > va);
+            }
+            Opcode::Inc => {
+                // inc(reg): add 1 in place, flags as for add
+                let target_reg = a & 0xFFF;
+                let res = self.read_reg(target_reg) as u32 + 1;
+                let max = if self.is_signed { 32767 } else { 65535 };
+                self.write_reg(target_reg, res as u16);
+                self.set_flag(O_OVERFLOW, res > max);
+            }
+            Opcode::Dec => {
+                // dec(reg): subtract 1 in place, flags as for sub
+                let target_reg = a & 0xFFF;
+                let val = self.read_reg(target_reg);
+                self.write_reg(target_reg, val.wrapping_sub(1));
+                self.set_flag(O_BORROW, val == 0);
+            }
+            Opcode::Ptrdiff => {
+                // ptrdiff(dst, a, b): signed distance a - b between two byte
+                // pointers, in 2-byte words (the only element size so far)
+                let target_reg = c & 0xFFF;
+                let words = va.wrapping_sub(vb) as i16 / 2;
+                self.write_reg(target_reg, words as u16);
+            }
+            Opcode::Cmp => {
+                // cmp(a, b): compare once, then branch on REG_ST with jz/jg/...
+                let ord = self.compare(va, vb);
+                let st = match ord {
+                    Ordering::Less => ST_LESS,
+                    Ordering::Equal => ST_ZERO,
+                    Ordering::Greater => ST_GREATER,
+                };
+                self.write_reg(REG_ST as u16, st);
+            }
+            Opcode::Test => {
+                // test(a, mask): ST = ST_ZERO if a & mask is 0; nothing is stored
+                let st = if va & vb == 0 { ST_ZERO } else { 0 };
+                self.write_reg(REG_ST as u16, st);
+            }
+            Opcode::Bset | Opcode::Bclr => {
+                // bset/bclr(reg, bit): set or clear one bit in place; the bit
+                // index is masked to 0..=15
+                let target_reg = a & 0xFFF;
+                let mask = 1 << (vb & 0xF);
+                let val = self.read_reg(target_reg);
+                let res = if op == Opcode::Bset {
+                    val | mask
+                } else {
+                    val & !mask
+                };
+                self.write_reg(target_reg, res);
+            }
+            Opcode::Btst => {
+                // btst(a, bit): like test with a single-bit mask
+                let st = if va & (1 << (vb & 0xF)) == 0 {
+                    ST_ZERO
+                } else {
+                    0
+                };
+                self.write_reg(REG_ST as u16, st);
+            }
+            Opcode::Setlt | Opcode::Sete | Opcode::Setgt => {
+                // set*(dst, a, b): dst = 1 if the comparison holds, else 0
+                let ord = self.compare(va, vb);
+                let holds = match op {
+                    Opcode::Setlt => ord.is_lt(),
+                    Opcode::Sete => ord.is_eq(),
+                    _ => ord.is_gt(),
+                };
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, holds as u16);
             }
             Opcode::Mul => {
+                // full 32-bit product: high word -> C, low word -> D
                 let res = (va as u32) * (vb as u32);
-                if res > 0xFFFF {
-                    self.write_reg(REG_C as u16, 0);
-                    self.write_reg(REG_D as u16, 0);
-                } else {
-                    self.write_reg(REG_C as u16, 0);
-                    self.write_reg(REG_D as u16, res as u16);
-                }
+                self.write_reg(REG_C as u16, (res >> 16) as u16);
+                self.write_reg(REG_D as u16, res as u16);
             }
             Opcode::And => {
                 let target_reg = c & 0xFFF;
                 self.write_reg(target_reg, va & vb);
             }
+            Opcode::Andn => {
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, va & !vb);
+            }
             Opcode::Or => {
                 let target_reg = c & 0xFFF;
                 self.write_reg(target_reg, va | vb);
@@ -233,35 +1118,21 @@ impl Emulator {
                 let target_reg = b & 0xFFF;
                 self.write_reg(target_reg, !va);
             }
-            Opcode::Jmp => self.write_reg(REG_IP as u16, vc),
-            Opcode::Jml => {
-                if va < vb {
-                    self.write_reg(REG_IP as u16, vc)
-                }
-            }
-            Opcode::Jmle => {
-                if va <= vb {
-                    self.write_reg(REG_IP as u16, vc)
-                }
-            }
-            Opcode::Jmb => {
-                if va > vb {
-                    self.write_reg(REG_IP as u16, vc)
-                }
-            }
-            Opcode::Jmbe => {
-                if va >= vb {
-                    self.write_reg(REG_IP as u16, vc)
-                }
-            }
-            Opcode::Jme => {
-                if va == vb {
-                    self.write_reg(REG_IP as u16, vc)
-                }
-            }
-            Opcode::Jmne => {
-                if va != vb {
-                    self.write_reg(REG_IP as u16, vc)
+            Opcode::Jmp
+            | Opcode::Jml
+            | Opcode::Jmle
+            | Opcode::Jmb
+            | Opcode::Jmbe
+            | Opcode::Jme
+            | Opcode::Jmne
+            | Opcode::Jz
+            | Opcode::Jnz
+            | Opcode::Jg
+            | Opcode::Jl
+            | Opcode::Jge
+            | Opcode::Jle => {
+                if let Some(target) = self.branch_target(op, va, vb, vc) {
+                    self.write_reg(REG_IP as u16, target);
                 }
             }
             Opcode::Save => {
@@ -269,54 +1140,921 @@ impl Emulator {
                 // va = src_value (what to store)
                 // vb = dest_addr_ptr (where to store it)
                 // Semantics: write va into memory at address vb
+                // The address is the operand alone; IP and MS/MO are never added,
+                // so address through MO by passing it as the operand (`save MO, A`).
                 let dest_addr = vb as usize;
                 self.write_mem_u16(dest_addr, va);
+                self.footprint.data_peak = self.footprint.data_peak.max(Some(dest_addr));
             }
             Opcode::Load => {
                 // MODIFIED: load(dest_reg, src_addr_ptr)
                 // vb = src_addr_ptr (where to read from)
                 // c = dest_reg (target register index)
                 // Semantics: read from memory at address vb, store in register c
+                // As with save, vb is the full address with no IP or MS/MO base.
                 let src_addr = vb as usize;
+                if self.is_uninitialized_read(src_addr) {
+                    return self.halt(HaltReason::UninitializedRead);
+                }
                 let val = self.read_mem_u16(src_addr);
                 let target_reg = c & 0xFFF;
                 self.write_reg(target_reg, val);
             }
+            Opcode::Saveb => {
+                // saveb(dest_addr_ptr, src_value): like save, but stores only
+                // the low byte of va at address vb. Addressed like save rather
+                // than at MS+MO, so `saveb MO, A` reaches the same byte
+                // `save MO, A` would start at
+                let dest_addr = vb as usize;
+                self.write_mem_u8(dest_addr, va as u8);
+                self.footprint.data_peak = self.footprint.data_peak.max(Some(dest_addr));
+            }
+            Opcode::Copy => {
+                // copy(src_addr, dest_addr, len): moves len words, addressed
+                // like save/load; overlapping ranges copy as if through a buffer
+                let (src, dest) = (va as usize, vb as usize);
+                let len = (vc as usize)
+                    .min(self.ram.len().saturating_sub(src) / 2)
+                    .min(self.ram.len().saturating_sub(dest) / 2);
+                if self.trap_uninitialized && (src..src + len * 2).any(|a| !self.is_written(a)) {
+                    return self.halt(HaltReason::UninitializedRead);
+                }
+                let words: Vec<u16> = (0..len).map(|i| self.read_mem_u16(src + i * 2)).collect();
+                for (i, word) in words.into_iter().enumerate() {
+                    self.write_mem_u16(dest + i * 2, word);
+                }
+                if len > 0 {
+                    let last = dest + (len - 1) * 2;
+                    self.footprint.data_peak = self.footprint.data_peak.max(Some(last));
+                }
+            }
+            Opcode::Fill => {
+                // fill(dest_addr, value, len): writes value to len words from
+                // dest_addr, stopping at the end of RAM
+                let dest = va as usize;
+                let len = (vc as usize).min(self.ram.len().saturating_sub(dest) / 2);
+                for i in 0..len {
+                    self.write_mem_u16(dest + i * 2, vb);
+                }
+                if len > 0 {
+                    let last = dest + (len - 1) * 2;
+                    self.footprint.data_peak = self.footprint.data_peak.max(Some(last));
+                }
+            }
+            Opcode::Loadb => {
+                // loadb(dest_reg, src_addr_ptr): like load, but reads the one
+                // byte at vb, zero-extended; no MS+MO base, as with saveb
+                let src_addr = vb as usize;
+                if self.trap_uninitialized
+                    && src_addr < self.ram.len()
+                    && !self.is_written(src_addr)
+                {
+                    return self.halt(HaltReason::UninitializedRead);
+                }
+                let val = self.ram.get(src_addr).copied().unwrap_or(0);
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, val as u16);
+            }
             Opcode::Push => {
-                let addr = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]) as usize;
-                self.write_mem_u16(addr, va);
-                self.regs[REG_SO] = self.regs[REG_SO].wrapping_add(2);
+                if let Err(reason) = self.push_word(va) {
+                    return self.halt(reason);
+                }
             }
             Opcode::Pop => {
-                self.regs[REG_SO] = self.regs[REG_SO].wrapping_sub(2);
-                let addr = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]) as usize;
+                let val = match self.pop_word() {
+                    Ok(val) => val,
+                    Err(reason) => return self.halt(reason),
+                };
+                let target_reg = a & 0xFFF;
+                self.write_reg(target_reg, val);
+            }
+            Opcode::Call => {
+                // IP already points past the call, which is the return address
+                if let Err(reason) = self.push_word(self.regs[REG_IP]) {
+                    return self.halt(reason);
+                }
+                self.write_reg(REG_IP as u16, vc);
+            }
+            Opcode::Ret => {
+                let ret = match self.pop_word() {
+                    Ok(ret) => ret,
+                    Err(reason) => return self.halt(reason),
+                };
+                self.write_reg(REG_IP as u16, ret);
+            }
+            Opcode::Peek => {
+                // peek(dest_reg, offset): word `offset` slots below the top, SO untouched
+                let top = self.regs[REG_SS].wrapping_add(self.regs[REG_SO]);
+                let addr = top.wrapping_sub(2).wrapping_sub(vb.wrapping_mul(2)) as usize;
+                if self.is_uninitialized_read(addr) {
+                    return self.halt(HaltReason::UninitializedRead);
+                }
                 let val = self.read_mem_u16(addr);
                 let target_reg = a & 0xFFF;
                 self.write_reg(target_reg, val);
             }
-            Opcode::Halt => return StepResult::Halt,
+            Opcode::Halt => return self.halt(HaltReason::Instruction),
+            // IP already advanced; nothing else happens
+            Opcode::Nop => {}
             Opcode::Shl => {
                 let target_reg = c & 0xFFF;
                 self.write_reg(target_reg, va << (vb & 15));
             }
             Opcode::Shr => {
                 let target_reg = c & 0xFFF;
-                self.write_reg(target_reg, va >> (vb & 15));
+                let res = if self.is_signed {
+                    // arithmetic shift: the sign bit is copied in
+                    ((va as i16) >> (vb & 15)) as u16
+                } else {
+                    va >> (vb & 15)
+                };
+                self.write_reg(target_reg, res);
+            }
+            Opcode::Rol => {
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, va.rotate_left((vb & 15) as u32));
+            }
+            Opcode::Ror => {
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, va.rotate_right((vb & 15) as u32));
+            }
+            Opcode::Rcl | Opcode::Rcr => {
+                // rcl/rcr(reg): rotate one bit through the carry flag, so a
+                // multi-word value shifts by chaining over its words
+                let target_reg = a & 0xFFF;
+                let val = self.read_reg(target_reg);
+                let carry_in = self.regs[REG_O] & O_CARRY != 0;
+                let (res, carry_out) = if op == Opcode::Rcl {
+                    ((val << 1) | carry_in as u16, val & 0x8000 != 0)
+                } else {
+                    ((val >> 1) | ((carry_in as u16) << 15), val & 1 != 0)
+                };
+                self.write_reg(target_reg, res);
+                self.set_flag(O_CARRY, carry_out);
+            }
+            Opcode::Loada => {
+                // loada(dest_reg, addr)
+                // vb = absolute address, c = dest_reg; MS/MO are left alone
+                if self.is_uninitialized_read(vb as usize) {
+                    return self.halt(HaltReason::UninitializedRead);
+                }
+                let val = self.read_mem_u16(vb as usize);
+                let target_reg = c & 0xFFF;
+                self.write_reg(target_reg, val);
+            }
+            Opcode::Div => {
+                // quotient -> C, remainder -> D; divide by zero leaves both alone
+                match (va.checked_div(vb), va.checked_rem(vb)) {
+                    (Some(quot), Some(rem)) => {
+                        self.write_reg(REG_C as u16, quot);
+                        self.write_reg(REG_D as u16, rem);
+                        self.set_flag(O_DIV_ERROR, false);
+                    }
+                    _ => self.set_flag(O_DIV_ERROR, true),
+                }
+            }
+            Opcode::Mod => {
+                let target_reg = c & 0xFFF;
+                match va.checked_rem(vb) {
+                    Some(rem) => {
+                        self.write_reg(target_reg, rem);
+                        self.set_flag(O_DIV_ERROR, false);
+                    }
+                    None => self.set_flag(O_DIV_ERROR, true),
+                }
+            }
+            Opcode::Mac => {
+                // C:D is a 32-bit accumulator (C high, D low); carry out of it sets overflow
+                let acc = ((self.regs[REG_C] as u32) << 16) | self.regs[REG_D] as u32;
+                let (sum, overflow) = acc.overflowing_add((va as u32) * (vb as u32));
+                self.write_reg(REG_C as u16, (sum >> 16) as u16);
+                self.write_reg(REG_D as u16, sum as u16);
+                self.set_flag(O_OVERFLOW, overflow);
+            }
+            Opcode::Dbgbreak => {
+                if self.debug_mode {
+                    self.debug_snapshot = Some(self.regs);
+                    return self.halt(HaltReason::DebugBreak);
+                }
+            }
+            Opcode::Rand => {
+                let val = self.next_rand();
+                let target_reg = a & 0xFFF;
+                self.write_reg(target_reg, val);
+            }
+        }
+
+        StepResult::Continue
+    }
+
+    pub fn step(&mut self) -> StepResult {
+        let ip = self.regs[REG_IP];
+        let result = self.step_inner();
+        if result == StepResult::Halt
+            && let Some((reason, halt_ip)) = &mut self.halt_reason
+        {
+            *halt_ip = ip;
+            if let Some(hook) = self.halt_hook.0.as_mut() {
+                hook(*reason, ip);
             }
         }
+        result
+    }
+
+    fn step_inner(&mut self) -> StepResult {
+        self.cycles += 1;
+
+        let ip = self.read_reg(REG_IP as u16);
+        let addr = ip as usize * 8;
+        // the whole 8-byte instruction must lie inside RAM
+        if addr + 8 > self.ram.len() {
+            return self.halt(HaltReason::OutOfBounds);
+        }
+
+        let DecodedInstruction {
+            opcode: op,
+            flags: f,
+            a,
+            b,
+            c,
+            ..
+        } = self.fetch(ip);
+
+        if self.strict
+            && let Some(message) = self.bad_register_operand(f, [a, b, c])
+        {
+            return self.strict_halt(message);
+        }
+
+        self.write_reg(REG_IP as u16, ip.wrapping_add(1));
+
+        if !self.input.is_empty()
+            && (0..3).any(|bit| (f >> bit) & 1 == 0 && [a, b, c][bit] & 0xFFF == REG_I as u16)
+            && let Some(value) = self.input.pop_front()
+        {
+            self.write_reg(REG_I as u16, value);
+        }
+
+        if self.trace_enabled {
+            self.record_trace(ip, op as u16);
+        }
+        if let Some(counts) = &mut self.opcode_counts {
+            counts[op as usize] += 1;
+        }
+
+        #[cfg(feature = "bench")]
+        let result = if self.table_dispatch {
+            DISPATCH[op as usize](self, f, a, b, c)
+        } else {
+            self.execute(op, f, a, b, c)
+        };
+        #[cfg(not(feature = "bench"))]
+        let result = self.execute(op, f, a, b, c);
+        if result != StepResult::Continue {
+            return result;
+        }
+
+        if self.strict
+            && let Some(message) = self.broken_invariant()
+        {
+            return self.strict_halt(message);
+        }
+
+        if !self.breakpoints.is_empty() && self.breakpoints.contains(&self.regs[REG_IP]) {
+            return StepResult::Breakpoint;
+        }
 
         StepResult::Continue
     }
 
+    // Steps until a non-Continue result or `max_steps`, returning that result
+    // (Continue when capped) and how many steps ran, the last one included.
+    pub fn run(&mut self, max_steps: u64) -> (StepResult, u64) {
+        for steps in 1..=max_steps {
+            let result = self.step();
+            if result != StepResult::Continue {
+                return (result, steps);
+            }
+        }
+        (StepResult::Continue, max_steps)
+    }
+
+    pub fn get_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // `run` with an optional wall-clock limit as well, whichever hits first.
+    pub fn run_with_timeout(
+        &mut self,
+        max_steps: u64,
+        timeout: Option<Duration>,
+    ) -> (RunOutcome, u64) {
+        let start = Instant::now();
+        let mut steps = 0;
+        while steps < max_steps {
+            let batch = (max_steps - steps).min(TIMEOUT_CHECK_INTERVAL);
+            let (result, ran) = self.run(batch);
+            steps += ran;
+            match result {
+                StepResult::Halt => return (RunOutcome::Halt, steps),
+                StepResult::Breakpoint => return (RunOutcome::Breakpoint, steps),
+                StepResult::Continue => {}
+            }
+            if timeout.is_some_and(|limit| start.elapsed() >= limit) {
+                return (RunOutcome::Timeout, steps);
+            }
+        }
+        (RunOutcome::StepLimit, steps)
+    }
+
+    // Runs until `n` steps have executed since reset, ignoring breakpoints.
+    // Returns true if the program halted first.
+    pub fn run_until_step(&mut self, n: u64) -> bool {
+        while self.cycles < n {
+            if self.step() == StepResult::Halt {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Steps until the program halts, at most `max_steps` times; false if the
+    // cap was reached first. Breakpoints don't stop it.
+    pub fn run_until_halt(&mut self, max_steps: u64) -> bool {
+        (0..max_steps).any(|_| self.step() == StepResult::Halt)
+    }
+
+    // Flat save-state: 12 little-endian registers, the signed flag byte, the
+    // cycle count (u64), the halt reason byte (0 = none) and halt IP (u16),
+    // RAM, then the written bitmap as little-endian u64s.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.snapshot_len());
+        for reg in self.regs {
+            data.extend_from_slice(&reg.to_le_bytes());
+        }
+        data.push(self.is_signed as u8);
+        data.extend_from_slice(&self.cycles.to_le_bytes());
+        let (reason, ip) = self
+            .halt_reason
+            .map_or((0, 0), |(reason, ip)| (reason as u8 + 1, ip));
+        data.push(reason);
+        data.extend_from_slice(&ip.to_le_bytes());
+        data.extend_from_slice(&self.ram[..]);
+        for bits in &self.written {
+            data.extend_from_slice(&bits.to_le_bytes());
+        }
+        data
+    }
+
+    fn snapshot_len(&self) -> usize {
+        NUM_REGS * 2 + 1 + 8 + 3 + self.ram.len() + self.written.len() * 8
+    }
+
+    // Puts back everything `snapshot` saved; false (and nothing changed) if
+    // `data` isn't a snapshot of an emulator with this memory size.
+    pub fn restore(&mut self, data: &[u8]) -> bool {
+        if data.len() != self.snapshot_len() {
+            return false;
+        }
+        let (regs, rest) = data.split_at(NUM_REGS * 2);
+        let (header, rest) = rest.split_at(12);
+        let halt_ip = u16::from_le_bytes([header[10], header[11]]);
+        let halt_reason = match header[9] {
+            0 => None,
+            n => match HALT_REASONS.get(n as usize - 1) {
+                Some(&reason) => Some((reason, halt_ip)),
+                None => return false,
+            },
+        };
+        for (reg, bytes) in self.regs.iter_mut().zip(regs.chunks_exact(2)) {
+            *reg = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        self.is_signed = header[0] != 0;
+        self.cycles = u64::from_le_bytes(header[1..9].try_into().unwrap());
+        self.halt_reason = halt_reason;
+        let (ram, written) = rest.split_at(self.ram.len());
+        self.ram.copy_from_slice(ram);
+        for (bits, bytes) in self.written.iter_mut().zip(written.chunks_exact(8)) {
+            *bits = u64::from_le_bytes(bytes.try_into().unwrap());
+        }
+        self.decoded.clear();
+        true
+    }
+
+    // Records (ip, opcode) for each executed step after which `predicate` holds.
+    pub fn run_traced_filtered<F>(&mut self, max_steps: usize, mut predicate: F) -> Vec<(u16, u16)>
+    where
+        F: FnMut(&Emulator) -> bool,
+    {
+        let mut trace = vec![];
+        for _ in 0..max_steps {
+            let ip = self.regs[REG_IP];
+            let opcode = self.read_mem_u16(ip as usize * 8) & 0x1FFF;
+            let result = self.step();
+            if predicate(self) {
+                trace.push((ip, opcode));
+            }
+            if result == StepResult::Halt {
+                break;
+            }
+        }
+        trace
+    }
+
+    pub fn registers(&self) -> [u16; NUM_REGS] {
+        self.regs
+    }
+
+    pub fn is_signed(&self) -> bool {
+        self.is_signed
+    }
+
+    // Sets bit 0 of O, leaving the other flag bits alone.
+    pub fn set_signed(&mut self, signed: bool) {
+        let o = self.regs[REG_O] & !1;
+        self.write_reg(REG_O as u16, o | signed as u16);
+    }
+
     pub fn get_state_string(&self) -> String {
-        format!(
-            "A  = {:#06X} ({})\nB  = {:#06X} ({})\nC  = {:#06X} ({})\nD  = {:#06X} ({})\nIP = {:#06X} ({})\nSS = {:#06X} ({})\nSO = {:#06X} ({})\nMS = {:#06X} ({})\nMO = {:#06X} ({})\nI  = {:#06X} ({})\nO  = {:#06X} ({})\nST = {:#06X} ({})",
-            self.regs[REG_A], self.regs[REG_A], self.regs[REG_B], self.regs[REG_B],
-            self.regs[REG_C], self.regs[REG_C], self.regs[REG_D], self.regs[REG_D],
-            self.regs[REG_IP], self.regs[REG_IP], self.regs[REG_SS], self.regs[REG_SS],
-            self.regs[REG_SO], self.regs[REG_SO], self.regs[REG_MS], self.regs[REG_MS],
-            self.regs[REG_MO], self.regs[REG_MO], self.regs[REG_I], self.regs[REG_I],
-            self.regs[REG_O], self.regs[REG_O], self.regs[REG_ST], self.regs[REG_ST],
+        REGISTER_NAMES
+            .iter()
+            .zip(self.registers())
+            .map(|(name, val)| format!("{:<2} = {:#06X} ({})", name, val, val))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub fn run_and_hash(program: &[u16], max_steps: usize) -> (u64, u32) {
+    let mut emu = Emulator::new();
+    emu.load_program_at_lossy(program, 0);
+
+    let mut steps = 0u64;
+    while (steps as usize) < max_steps {
+        steps += 1;
+        if emu.step() == StepResult::Halt {
+            break;
+        }
+    }
+
+    // FNV-1a over the whole RAM image.
+    let mut hash: u32 = 0x811C9DC5;
+    for byte in emu.ram.iter() {
+        hash ^= *byte as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+
+    (steps, hash)
+}
+
+// Counts opcodes in assembled words, one header per 4-word instruction.
+pub fn static_opcode_histogram(program: &[u16]) -> HashMap<Opcode, usize> {
+    let mut counts = HashMap::new();
+    for instr in program.chunks_exact(4) {
+        *counts.entry(Opcode::from(instr[0] & 0x1FFF)).or_insert(0) += 1;
+    }
+    counts
+}
+
+// Function-table alternative to the opcode match in `execute`, kept for
+// benches/dispatch.rs. Each handler inlines `execute` with a constant opcode,
+// so its match folds to one arm. On the arithmetic-loop bench it measured
+// within noise of the match (about 1%), so `step` keeps the match.
+#[cfg(feature = "bench")]
+type Handler = fn(&mut Emulator, u16, u16, u16, u16) -> StepResult;
+
+#[cfg(feature = "bench")]
+fn handler<const OP: u16>(emu: &mut Emulator, f: u16, a: u16, b: u16, c: u16) -> StepResult {
+    emu.execute(Opcode::from(OP), f, a, b, c)
+}
+
+#[cfg(feature = "bench")]
+macro_rules! dispatch_table {
+    ($($op:literal)*) => {
+        [$(handler::<$op> as Handler),*]
+    };
+}
+
+// Indexed by `Opcode as usize`; needs one entry per opcode.
+#[cfg(feature = "bench")]
+const DISPATCH: [Handler; NUM_OPCODES] = dispatch_table!(
+    0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24
+    25 26 27 28 29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49
+    50 51 52 53 54 55 56 57
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neozasm::assemble;
+
+    fn run(source: &str, steps: u64) -> Emulator {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble(source).unwrap()).unwrap();
+        emu.run(steps);
+        emu
+    }
+
+    #[test]
+    fn run_and_hash_is_deterministic() {
+        let a = assemble("mov 5, A\nsave 100, A\n").unwrap();
+        let b = assemble("mov 6, A\nsave 100, A\n").unwrap();
+        assert_eq!(run_and_hash(&a, 100), run_and_hash(&a, 100));
+        assert_ne!(run_and_hash(&a, 100).1, run_and_hash(&b, 100).1);
+        // two instructions plus the trailing halt
+        assert_eq!(run_and_hash(&a, 100).0, 3);
+    }
+
+    #[test]
+    fn loada_reads_absolute_address_and_keeps_mo() {
+        // 0x8100 is the byte address of instruction slot 0x1020
+        let emu = run(
+            "mov 7, MO\nmov 0x8100, B\nloada A, 0x8100\nloada C, B\nhalt\norg 0x1020\ndw 0xBEEF\n",
+            10,
+        );
+        assert_eq!(emu.get_register(REG_A), 0xBEEF);
+        assert_eq!(emu.get_register(REG_C), 0xBEEF);
+        assert_eq!(emu.get_register(REG_MO), 7);
+    }
+
+    #[test]
+    fn mul_splits_product_across_c_and_d() {
+        let emu = run("mul 0x1000, 0x1000\n", 1);
+        assert_eq!(emu.get_register(REG_C), 0x0100);
+        assert_eq!(emu.get_register(REG_D), 0x0000);
+
+        let emu = run("mul 4000, 4000\n", 1);
+        assert_eq!(emu.get_register(REG_C), 0x00F4);
+        assert_eq!(emu.get_register(REG_D), 0x2400);
+    }
+
+    #[test]
+    fn restore_rewinds_cycles_halt_and_written() {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble("mov 1, A\nsave 0x8000, A\nadd A, 1, A\nhalt\n").unwrap())
+            .unwrap();
+        emu.run(1);
+        let saved = emu.snapshot();
+        let before = emu.clone();
+
+        emu.run(10);
+        assert!(emu.halt_reason() == Some(HaltReason::Instruction));
+        assert!(emu.restore(&saved));
+        assert_eq!(emu.registers(), before.registers());
+        assert_eq!(emu.get_cycles(), before.get_cycles());
+        assert!(emu.halt_reason().is_none());
+        assert_eq!(emu.ram_slice(), before.ram_slice());
+        // the save at 0x8000 is undone
+        assert!(!emu.is_written(0x8000));
+        assert_eq!(emu.snapshot(), saved);
+
+        assert!(!emu.restore(&saved[1..]));
+    }
+
+    #[test]
+    fn disassemble_at_matches_execution_for_wide_immediates() {
+        let emu = run("mov 0xF00F, A\nadd A, 0x9001, B\n", 2);
+        assert_eq!(emu.disassemble_at(0), "mov 61455, A");
+        assert_eq!(emu.disassemble_at(8), "add A, 36865, B");
+        assert_eq!(emu.get_register(REG_A), 61455);
+        assert_eq!(emu.get_register(REG_B), 61455u16.wrapping_add(36865));
+
+        // the disassembly runs the same as the original
+        let listing = format!("{}\n{}\n", emu.disassemble_at(0), emu.disassemble_at(8));
+        assert_eq!(run(&listing, 2).registers(), emu.registers());
+    }
+
+    #[test]
+    fn run_with_timeout_reports_why_it_stopped() {
+        let spin = assemble("spin:\njmp spin\n").unwrap();
+        let mut emu = Emulator::new();
+        emu.load_program(&spin).unwrap();
+        assert_eq!(
+            emu.run_with_timeout(100, None),
+            (RunOutcome::StepLimit, 100)
+        );
+        // the clock is checked between batches, so a zero timeout still runs one
+        assert_eq!(
+            emu.run_with_timeout(u64::MAX, Some(Duration::ZERO)),
+            (RunOutcome::Timeout, TIMEOUT_CHECK_INTERVAL)
+        );
+        assert_eq!(emu.get_cycles(), 100 + TIMEOUT_CHECK_INTERVAL);
+
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble("nop\nhalt\n").unwrap()).unwrap();
+        assert_eq!(
+            emu.run_with_timeout(100, Some(Duration::from_secs(60))),
+            (RunOutcome::Halt, 2)
+        );
+    }
+
+    #[test]
+    fn writes_into_code_invalidate_the_decode_cache() {
+        // the save patches the immediate of the mov after it
+        let program = assemble("save patch * 8 + 2, 42\npatch:\nmov 1, A\n").unwrap();
+        let mut emu = Emulator::new();
+        emu.load_program(&program).unwrap();
+        emu.precompile();
+        let decodes = emu.decode_count();
+        emu.run(10);
+        assert_eq!(emu.get_register(REG_A), 42);
+        // only the patched slot was decoded again
+        assert_eq!(emu.decode_count(), decodes + 1);
+    }
+
+    #[test]
+    fn halt_hook_gets_the_halting_instruction() {
+        use std::sync::{Arc, Mutex};
+
+        let halts = Arc::new(Mutex::new(vec![]));
+        let record = |emu: &mut Emulator| {
+            let halts = Arc::clone(&halts);
+            emu.set_halt_hook(Box::new(move |reason, ip| {
+                halts.lock().unwrap().push((reason, ip))
+            }));
+        };
+
+        let mut emu = Emulator::new();
+        record(&mut emu);
+        emu.load_program(&assemble("nop\nhalt\n").unwrap()).unwrap();
+        emu.run(10);
+        assert_eq!(emu.halt_ip(), Some(1));
+        // IP has already moved past the halt
+        assert_eq!(emu.get_ip(), 2);
+
+        let mut emu = Emulator::with_memory_size(256);
+        record(&mut emu);
+        emu.load_program(&assemble("jmp 40\n").unwrap()).unwrap();
+        emu.run(10);
+        assert_eq!(emu.halt_ip(), Some(40));
+
+        assert_eq!(
+            *halts.lock().unwrap(),
+            [(HaltReason::Instruction, 1), (HaltReason::OutOfBounds, 40)]
+        );
+
+        // the hook survives reset, and stops after clear_halt_hook
+        emu.reset();
+        emu.set_ip(50);
+        emu.step();
+        assert_eq!(halts.lock().unwrap().len(), 3);
+        emu.clear_halt_hook();
+        emu.step();
+        assert_eq!(halts.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn cmp_flags_follow_signed_mode() {
+        // 0xFFFF is 65535 unsigned but -1 signed
+        let source = "mov 0xFFFF, A\ncmp A, 1\njl less\nmov 1, B\nhalt\nless:\nmov 2, B\n";
+        let program = assemble(source).unwrap();
+        let run_in = |signed: bool| {
+            let mut emu = Emulator::new();
+            emu.load_program(&program).unwrap();
+            emu.set_signed(signed);
+            emu.run(10);
+            emu
+        };
+
+        let unsigned = run_in(false);
+        assert_eq!(unsigned.get_register(REG_ST), ST_GREATER);
+        assert_eq!(unsigned.get_register(REG_B), 1);
+        let signed = run_in(true);
+        assert_eq!(signed.get_register(REG_ST), ST_LESS);
+        assert_eq!(signed.get_register(REG_B), 2);
+
+        // equal values set ZERO either way
+        let emu = run("mov 0xFFFF, A\ncmp A, 0xFFFF\n", 2);
+        assert_eq!(emu.get_register(REG_ST), ST_ZERO);
+    }
+
+    #[test]
+    fn nested_calls_return_in_order() {
+        let emu = run(
+            "call outer\nmov 9, D\nhalt\n\
+             outer:\nmov 1, A\ncall inner\nadd A, 10, A\nret\n\
+             inner:\nadd A, 100, A\nret\n",
+            20,
+        );
+        assert_eq!(emu.get_register(REG_A), 111);
+        assert_eq!(emu.get_register(REG_D), 9);
+        assert_eq!(emu.get_register(REG_SO), 0);
+        assert_eq!(emu.halt_ip(), Some(2));
+
+        // nothing to return to
+        let emu = run("ret\nmov 1, A\n", 10);
+        assert_eq!(emu.halt_reason(), Some(HaltReason::StackUnderflow));
+        assert_eq!(emu.get_register(REG_O) & O_STACK_FAULT, O_STACK_FAULT);
+        assert_eq!(emu.get_register(REG_A), 0);
+    }
+
+    #[test]
+    fn rotates_wrap_bits_around() {
+        let emu = run("rol 0x8001, 1, A\nror 0x8001, 1, B\n", 2);
+        assert_eq!(emu.get_register(REG_A), 0x0003);
+        assert_eq!(emu.get_register(REG_B), 0xC000);
+
+        // counts are taken mod 16: 0 and 16 leave the value alone, 17 is 1
+        let emu = run(
+            "rol 0x8001, 0, A\nror 0x8001, 16, B\nrol 0x8001, 17, C\n",
+            3,
+        );
+        assert_eq!(emu.get_register(REG_A), 0x8001);
+        assert_eq!(emu.get_register(REG_B), 0x8001);
+        assert_eq!(emu.get_register(REG_C), 0x0003);
+    }
+
+    #[test]
+    fn mmio_writes_are_queued_and_reads_see_ram() {
+        let mut emu = Emulator::new();
+        emu.set_mmio_range(0x9000, 4);
+        // the host's device state, read back by the program
+        emu.write_ram(0x9000, &[0x34, 0x12]);
+        emu.load_program(
+            &assemble("save 0x9000, 0xBEEF\nsaveb 0x9003, 7\nsave 0x9004, 5\nload A, 0x9000\n")
+                .unwrap(),
         )
+        .unwrap();
+        emu.run(10);
+
+        assert_eq!(emu.drain_mmio_events(), [(0x9000, 0xBEEF), (0x9003, 7)]);
+        assert!(emu.drain_mmio_events().is_empty());
+        assert_eq!(emu.get_register(REG_A), 0x1234);
+        assert_eq!(emu.ram_slice()[0x9000..0x9006], [0x34, 0x12, 0, 0, 5, 0]);
+    }
+
+    #[test]
+    fn input_queue_feeds_i_one_read_at_a_time() {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble("mov I, A\nnop\nmov I, B\nmov I, C\n").unwrap())
+            .unwrap();
+        emu.push_input(10);
+        emu.push_input(20);
+        emu.run(2);
+        assert_eq!(emu.get_register(REG_A), 10);
+        // the nop doesn't read I, so 20 is still queued
+        assert_eq!(emu.get_register(REG_I), 10);
+        emu.run(10);
+        assert_eq!(emu.get_register(REG_B), 20);
+        // queue empty: I keeps its last value
+        assert_eq!(emu.get_register(REG_C), 20);
+
+        let mut emu = Emulator::new();
+        emu.set_input(7);
+        emu.load_program(&assemble("mov I, A\n").unwrap()).unwrap();
+        emu.run(2);
+        assert_eq!(emu.get_register(REG_A), 7);
+    }
+
+    #[test]
+    fn stack_faults_set_o_and_halt() {
+        // two words fit under a 4-byte limit; the third push overflows
+        let mut emu = Emulator::new();
+        emu.set_stack_limit(4);
+        emu.load_program(&assemble("push 1\npush 2\npush 3\nmov 1, A\n").unwrap())
+            .unwrap();
+        emu.run(10);
+        assert_eq!(emu.halt_reason(), Some(HaltReason::StackOverflow));
+        assert_eq!(emu.halt_ip(), Some(2));
+        assert_eq!(emu.get_register(REG_O) & O_STACK_FAULT, O_STACK_FAULT);
+        assert_eq!(emu.get_register(REG_SO), 4);
+        assert_eq!(emu.get_register(REG_A), 0);
+
+        let emu = run("pop A\nmov 1, B\n", 10);
+        assert_eq!(emu.halt_reason(), Some(HaltReason::StackUnderflow));
+        assert_eq!(emu.get_register(REG_O) & O_STACK_FAULT, O_STACK_FAULT);
+        assert_eq!(emu.get_register(REG_SO), 0);
+        assert_eq!(emu.get_register(REG_B), 0);
+
+        // a balanced push/pop leaves the bit clear
+        let emu = run("push 5\npop A\n", 10);
+        assert_eq!(emu.get_register(REG_A), 5);
+        assert_eq!(emu.get_register(REG_O) & O_STACK_FAULT, 0);
+    }
+
+    #[test]
+    fn byte_access_hits_one_byte_of_a_word() {
+        let emu = run(
+            "save 0x500, 0x1234\nloadb A, 0x500\nloadb B, 0x501\n\
+             saveb 0x511, 0xABCD\nsave 0x520, 0x1234\nsaveb 0x521, 0xAB\nload C, 0x520\n",
+            10,
+        );
+        // little-endian: the low byte comes first
+        assert_eq!(emu.get_register(REG_A), 0x34);
+        assert_eq!(emu.get_register(REG_B), 0x12);
+        // only the low byte of the value is stored, and only at that address
+        assert_eq!(emu.ram_slice()[0x510..0x513], [0, 0xCD, 0]);
+        assert_eq!(emu.get_register(REG_C), 0xAB34);
+
+        // the operand is the whole address, with no MS+MO added
+        let emu = run("mov 0x10, MO\nsaveb 0x600, 0xAB\nloadb A, 0x600\n", 10);
+        assert_eq!(emu.ram_slice()[0x600], 0xAB);
+        assert_eq!(emu.get_register(REG_A), 0xAB);
+    }
+
+    #[test]
+    fn copy_handles_overlap_in_both_directions() {
+        let setup = "save 0x500, 1\nsave 0x502, 2\nsave 0x504, 3\nsave 0x506, 4\n";
+        let words = |emu: &Emulator, start: usize, n: usize| -> Vec<u16> {
+            emu.ram_slice()[start..start + n * 2]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect()
+        };
+
+        // forward: dest above src
+        let emu = run(&format!("{}copy 0x500, 0x502, 4\n", setup), 10);
+        assert_eq!(words(&emu, 0x500, 5), [1, 1, 2, 3, 4]);
+        // backward: dest below src
+        let emu = run(&format!("{}copy 0x502, 0x500, 3\n", setup), 10);
+        assert_eq!(words(&emu, 0x500, 4), [2, 3, 4, 4]);
+    }
+
+    #[test]
+    fn fill_writes_a_full_word_and_stays_in_range() {
+        let emu = run("save 0x4FE, 7\nsave 0x510, 9\nfill 0x500, 0xEEEE, 8\n", 10);
+        let ram = emu.ram_slice();
+        assert!(ram[0x500..0x510].iter().all(|&b| b == 0xEE));
+        // the words on either side are untouched
+        assert_eq!(ram[0x4FE..0x500], [7, 0]);
+        assert_eq!(ram[0x510..0x512], [9, 0]);
+
+        // a count past the end of RAM is clamped
+        let mut emu = Emulator::with_memory_size(0x600);
+        emu.load_program(&assemble("fill 0x5FC, 3, 100\n").unwrap())
+            .unwrap();
+        emu.run(5);
+        assert_eq!(emu.ram_slice()[0x5FC..], [3, 0, 3, 0]);
+    }
+
+    #[test]
+    fn fork_runs_independently() {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble("inc A\ninc A\nsave 0x500, A\nhalt\n").unwrap())
+            .unwrap();
+        emu.step();
+        let mut fork = emu.fork();
+        fork.run(10);
+        assert_eq!(fork.get_register(REG_A), 2);
+        assert_eq!(fork.ram_slice()[0x500], 2);
+
+        // the original is still after its first step
+        assert_eq!(emu.get_register(REG_A), 1);
+        assert_eq!(emu.get_ip(), 1);
+        assert_eq!(emu.get_cycles(), 1);
+        assert_eq!(emu.ram_slice()[0x500], 0);
+        assert!(emu.halt_reason().is_none());
+        // and carries on by itself
+        emu.run(10);
+        assert_eq!(emu.registers(), fork.registers());
+    }
+
+    #[test]
+    fn set_ip_picks_the_next_instruction() {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble("mov 1, A\nmov 2, A\nmov 3, A\nmov 4, A\n").unwrap())
+            .unwrap();
+        emu.set_ip(3);
+        // instruction 3 starts at byte 24
+        assert_eq!(emu.disassemble_at(24), "mov 4, A");
+        emu.step();
+        assert_eq!(emu.get_register(REG_A), 4);
+        assert_eq!(emu.get_ip(), 4);
+
+        // past the end of RAM the next step halts without running anything
+        let mut emu = Emulator::with_memory_size(256);
+        emu.set_ip(32);
+        assert!(emu.step() == StepResult::Halt);
+        assert_eq!(emu.halt_reason(), Some(HaltReason::OutOfBounds));
+        assert_eq!(emu.get_ip(), 32);
+    }
+
+    #[test]
+    fn stack_contents_lists_bottom_first() {
+        assert!(Emulator::new().stack_contents().is_empty());
+        let emu = run("push 10\npush 20\npush 30\n", 3);
+        assert_eq!(emu.stack_contents(), [10, 20, 30]);
+        let emu = run("push 10\npush 20\npush 30\npop A\n", 4);
+        assert_eq!(emu.stack_contents(), [10, 20]);
+        assert_eq!(emu.get_register(REG_A), 30);
+    }
+
+    #[test]
+    fn fetch_needs_the_whole_instruction_in_ram() {
+        // the last slot of a 64-byte memory is bytes 56..64
+        let mut emu = Emulator::with_memory_size(64);
+        emu.load_program_at(&assemble("mov 5, A\n").unwrap()[..4], 28)
+            .unwrap();
+        emu.set_ip(7);
+        assert!(emu.step() == StepResult::Continue);
+        assert_eq!(emu.get_register(REG_A), 5);
+        assert!(emu.step() == StepResult::Halt);
+        assert_eq!(emu.halt_ip(), Some(8));
+
+        // a slot cut short by the end of RAM isn't fetched at all
+        let mut emu = Emulator::with_memory_size(63);
+        emu.set_ip(7);
+        assert!(emu.step() == StepResult::Halt);
+        assert_eq!(emu.halt_reason(), Some(HaltReason::OutOfBounds));
     }
 }
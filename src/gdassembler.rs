@@ -1,20 +1,82 @@
 use godot::classes::Node;
 use godot::prelude::*;
+use std::collections::HashMap;
 
-use crate::neozasm::assemble as assemblenz;
+use crate::emulator::{REGISTER_NAMES, static_opcode_histogram};
+use crate::gdemulator::to_words;
+use crate::neozasm::{
+    Severity, SourceMap, SymbolKind, assemble_with_listing, assemble_with_source_map,
+    assemble_with_symbols, check as checknz, disassemble_listing, mnemonic, program_to_json,
+    symbols as symbolsnz,
+};
+
+// Source line -> IP of the first slot it emitted (a `times` line maps to
+// several); lines without code are absent.
+fn line_ips(map: SourceMap) -> HashMap<usize, u16> {
+    let mut ips = HashMap::new();
+    for (line, ip) in map {
+        ips.entry(line).or_insert(ip);
+    }
+    ips
+}
+
+// IP for a 1-based source line, or -1 for a comment, label or blank line.
+fn ip_for_line(line_ips: &HashMap<usize, u16>, line: i64) -> i64 {
+    usize::try_from(line)
+        .ok()
+        .and_then(|line| line_ips.get(&line))
+        .map_or(-1, |&ip| ip as i64)
+}
+
+// `kind` string of a symbol dictionary.
+fn kind_name(kind: SymbolKind) -> &'static str {
+    match kind {
+        SymbolKind::Label => "label",
+        SymbolKind::Const => "const",
+        SymbolKind::Alias => "alias",
+    }
+}
+
+// name -> value pairs; entries of the wrong type are skipped.
+fn to_symbol_map(symbols: &Dictionary) -> HashMap<String, u16> {
+    symbols
+        .iter_shared()
+        .filter_map(|(name, value)| {
+            Some((
+                name.try_to::<String>().ok()?,
+                value.try_to::<i64>().ok()? as u16,
+            ))
+        })
+        .collect()
+}
 
 #[derive(GodotClass)]
 #[class(base=Node, init)]
 struct AssemblrNode {
     #[base]
     base: Base<Node>,
+
+    // source line -> IP from the last `assemble`; empty after a failure
+    line_ips: HashMap<usize, u16>,
+    // "<message> on line <n>" from the last failed `assemble`, else empty
+    last_error: String,
 }
 
 #[godot_api]
 impl AssemblrNode {
+    // Returns an empty array on failure; see `last_error`.
     #[func]
     fn assemble(&mut self, source: String) -> PackedByteArray {
-        let result: Vec<u16> = assemblenz(&source);
+        let (result, map) = match assemble_with_source_map(&source) {
+            Ok(assembled) => assembled,
+            Err(err) => {
+                self.last_error = err.to_string();
+                self.line_ips.clear();
+                return PackedByteArray::new();
+            }
+        };
+        self.last_error.clear();
+        self.line_ips = line_ips(map);
 
         let mut byte_vec = Vec::with_capacity(result.len() * 2);
         for word in result {
@@ -24,4 +86,152 @@ impl AssemblrNode {
 
         PackedByteArray::from(byte_vec)
     }
+
+    // Like `assemble`, but one array entry per 16-bit word.
+    #[func]
+    fn assemble_words(&mut self, source: String) -> PackedInt32Array {
+        let bytes = self.assemble(source);
+        to_words(bytes.as_slice())
+            .into_iter()
+            .map(i32::from)
+            .collect()
+    }
+
+    // Address / words / source listing of `source`, or the first error.
+    #[func]
+    fn assemble_listing(&mut self, source: String) -> String {
+        assemble_with_listing(&source).1
+    }
+
+    #[func]
+    fn last_error(&self) -> String {
+        self.last_error.clone()
+    }
+
+    #[func]
+    fn line_to_ip(&self, line: i64) -> i64 {
+        ip_for_line(&self.line_ips, line)
+    }
+
+    #[func]
+    fn check(&mut self, source: String) -> Array<Dictionary> {
+        let mut diagnostics = Array::new();
+
+        for diag in checknz(&source) {
+            let severity = match diag.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Info => "info",
+            };
+
+            let mut dict = Dictionary::new();
+            dict.set("severity", severity);
+            dict.set("line", diag.line as i64);
+            dict.set("message", diag.message);
+            diagnostics.push(&dict);
+        }
+
+        diagnostics
+    }
+
+    #[func]
+    fn symbols(&mut self, source: String) -> Array<Dictionary> {
+        let mut symbols = Array::new();
+
+        for symbol in symbolsnz(&source) {
+            let mut dict = Dictionary::new();
+            dict.set("name", symbol.name);
+            dict.set("value", symbol.value as i64);
+            dict.set("kind", kind_name(symbol.kind));
+            if symbol.kind == SymbolKind::Alias {
+                // aliases also carry the register they stand for, e.g. "B"
+                let register = REGISTER_NAMES.get(symbol.value as usize).copied();
+                dict.set("register", register.unwrap_or_default());
+            }
+            symbols.push(&dict);
+        }
+
+        symbols
+    }
+
+    // name -> value for every label (instruction address) and const.
+    #[func]
+    fn assemble_symbols(&mut self, source: String) -> Dictionary {
+        let mut dict = Dictionary::new();
+        for (name, value) in assemble_with_symbols(&source).1 {
+            dict.set(name, value as i64);
+        }
+        dict
+    }
+
+    #[func]
+    fn disassemble_listing(&mut self, program: PackedByteArray, symbols: Dictionary) -> String {
+        let words = to_words(program.as_slice());
+
+        let symbols = to_symbol_map(&symbols);
+
+        disassemble_listing(&words, (!symbols.is_empty()).then_some(&symbols))
+    }
+
+    #[func]
+    fn program_to_json(&mut self, program: PackedByteArray, symbols: Dictionary) -> String {
+        let words = to_words(program.as_slice());
+
+        let symbols = to_symbol_map(&symbols);
+
+        program_to_json(&words, (!symbols.is_empty()).then_some(&symbols))
+    }
+
+    #[func]
+    fn opcode_histogram(&mut self, program: PackedByteArray) -> Dictionary {
+        let words = to_words(program.as_slice());
+
+        let mut dict = Dictionary::new();
+        for (op, count) in static_opcode_histogram(&words) {
+            let name = mnemonic(op as u16).unwrap_or("???");
+            dict.set(name, count as i64);
+        }
+        dict
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neozasm::source_map;
+
+    #[test]
+    fn line_to_ip_skips_lines_without_code() {
+        let ips = line_ips(source_map("start:\nmov 1, A\n; note\n\nnop\n"));
+        assert_eq!(ip_for_line(&ips, 2), 0);
+        assert_eq!(ip_for_line(&ips, 5), 1);
+        // label, comment, blank, past the end, negative
+        for line in [1, 3, 4, 6, -1] {
+            assert_eq!(ip_for_line(&ips, line), -1);
+        }
+    }
+
+    #[test]
+    fn times_line_maps_to_its_first_ip() {
+        let ips = line_ips(source_map("times 3 nop\nnop\n"));
+        assert_eq!(ip_for_line(&ips, 1), 0);
+        assert_eq!(ip_for_line(&ips, 2), 3);
+    }
+
+    #[test]
+    fn alias_symbols_get_their_own_kind() {
+        let kinds: Vec<(String, &str)> =
+            symbolsnz("alias COUNTER: B\nconst K: 1\nstart:\nmov K, COUNTER\n")
+                .into_iter()
+                .map(|symbol| (symbol.name, kind_name(symbol.kind)))
+                .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("COUNTER".to_string(), "alias"),
+                ("K".to_string(), "const"),
+                ("start".to_string(), "label"),
+            ]
+        );
+    }
 }
@@ -1,7 +1,32 @@
-use crate::emulator::{self as emu_module, StepResult};
+use crate::emulator::{
+    self as emu_module, HaltReason, NUM_REGS, REG_IP, REGISTER_NAMES, RunOutcome, StepResult,
+    register_index,
+};
 use godot::classes::Node;
 use godot::prelude::*;
-use std::time::Instant; // Avoid name conflict
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH}; // Avoid name conflict
+
+// Little-endian byte pairs, as produced by AssemblrNode.assemble; an odd
+// trailing byte is dropped.
+pub(crate) fn to_words(bytes: &[u8]) -> Vec<u16> {
+    bytes
+        .chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect()
+}
+
+// Counts `seed_from_time` calls, so seeds taken in the same clock tick differ.
+static SEED_CALLS: AtomicU64 = AtomicU64::new(0);
+
+// Clock nanoseconds mixed with a per-node salt (instance id and call count),
+// so two nodes seeded in the same tick still get different streams.
+fn time_seed(nanos: u128, salt: u64) -> u32 {
+    let mixed = (nanos ^ (nanos >> 64)) as u64 ^ salt.wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    (mixed ^ (mixed >> 32)) as u32
+}
 
 #[derive(GodotClass)]
 #[class(base=Node)]
@@ -10,6 +35,14 @@ struct EmulatorNode {
     base: Base<Node>,
 
     emu: emu_module::Emulator,
+    // set while `run_async` runs the emulator on a worker thread; `emu` then
+    // holds a snapshot from the start of the run
+    worker: Option<JoinHandle<(emu_module::Emulator, RunOutcome, u64)>>,
+    // filled by the halt hook while `set_halt_logging` is on, drained into `halt_logged`
+    halt_events: Arc<Mutex<Vec<(HaltReason, u16)>>>,
+    // when set, `step()` emits `register_changed` for every register it altered
+    #[var]
+    notify_register_changes: bool,
 }
 #[godot_api]
 impl INode for EmulatorNode {
@@ -17,75 +50,658 @@ impl INode for EmulatorNode {
         godot_print!("Initializing!");
         godot_print!("Initialized! i think...?");
         Self {
-            base: base,
+            base,
             emu: emu_module::Emulator::default(),
+            worker: None,
+            halt_events: Arc::default(),
+            notify_register_changes: false,
+        }
+    }
+
+    fn process(&mut self, _delta: f64) {
+        if !self.worker.as_ref().is_some_and(|w| w.is_finished()) {
+            return;
+        }
+        let Some(worker) = self.worker.take() else {
+            return;
+        };
+        match worker.join() {
+            Ok((emu, outcome, steps)) => {
+                self.emu = emu;
+                self.flush_halt_events();
+                if outcome == RunOutcome::Halt {
+                    self.emit_halted();
+                }
+                self.signals()
+                    .run_finished()
+                    .emit(outcome.as_str(), steps as i64);
+            }
+            Err(_) => {
+                godot_error!("run_async worker panicked; keeping the state from before the run");
+            }
         }
     }
 }
 #[godot_api]
 impl EmulatorNode {
+    #[signal]
+    fn breakpoint_hit(ip: i64);
+    // the program halted (for any reason); `ip` is the instruction that
+    // halted, the same IP `halt_logged` reports
+    #[signal]
+    fn halted(ip: i64);
+    #[signal]
+    fn run_finished(reason: GString, steps: i64);
+    #[signal]
+    fn halt_logged(reason: GString, ip: i64);
+    #[signal]
+    fn register_changed(name: GString, old: i64, new: i64);
+
+    // Loads nothing and returns false if the program would run past RAM.
     #[func] // Makes it accessible from GDScript
-    fn load_program(&mut self, program: PackedByteArray) {
-        let vec: Vec<u16> = program
+    fn load_program(&mut self, program: PackedByteArray) -> bool {
+        if self.refuse_while_running("load_program") {
+            return false;
+        }
+        self.emu.load_program(&to_words(program.as_slice())).is_ok()
+    }
+    // Same as load_program, for word arrays such as AssemblrNode.assemble_words
+    // output. Also loads nothing and returns false if a value doesn't fit in
+    // 16 bits.
+    #[func]
+    fn load_program_words(&mut self, words: PackedInt32Array) -> bool {
+        if self.refuse_while_running("load_program_words") {
+            return false;
+        }
+        let Ok(words) = words
             .as_slice()
-            .chunks_exact(2)
-            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-            .collect();
-
-        self.emu.load_program(&vec);
+            .iter()
+            .map(|&word| u16::try_from(word))
+            .collect::<Result<Vec<u16>, _>>()
+        else {
+            godot_error!("load_program_words: every word must be in 0..=65535");
+            return false;
+        };
+        self.emu.load_program(&words).is_ok()
+    }
+    // Loads nothing and returns false if the program would run past RAM. Set
+    // IP to word_offset / 4 to run code loaded this way.
+    #[func]
+    fn load_program_at(&mut self, program: PackedByteArray, word_offset: i64) -> bool {
+        if self.refuse_while_running("load_program_at") {
+            return false;
+        }
+        self.emu
+            .load_program_at(&to_words(program.as_slice()), word_offset.max(0) as usize)
+            .is_ok()
+    }
+    // Loads whatever fits and drops the rest.
+    #[func]
+    fn load_program_at_lossy(&mut self, program: PackedByteArray, word_offset: i64) {
+        if self.refuse_while_running("load_program_at_lossy") {
+            return;
+        }
+        self.emu
+            .load_program_at_lossy(&to_words(program.as_slice()), word_offset.max(0) as usize);
     }
     #[func]
     fn reset(&mut self) {
+        if self.refuse_while_running("reset") {
+            return;
+        }
         self.emu.reset();
     }
     #[func]
+    fn add_breakpoint(&mut self, ip: i64) {
+        if self.refuse_while_running("add_breakpoint") {
+            return;
+        }
+        self.emu.add_breakpoint(ip as u16);
+    }
+    #[func]
+    fn remove_breakpoint(&mut self, ip: i64) {
+        if self.refuse_while_running("remove_breakpoint") {
+            return;
+        }
+        self.emu.remove_breakpoint(ip as u16);
+    }
+    #[func]
+    fn clear_general(&mut self) {
+        if self.refuse_while_running("clear_general") {
+            return;
+        }
+        // A, B, C, D
+        self.emu.reset_registers(0b1111);
+    }
+    // Emits `halt_logged(reason, ip)` for every halt while enabled.
+    #[func]
+    fn set_halt_logging(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_halt_logging") {
+            return;
+        }
+        if enabled {
+            let events = Arc::clone(&self.halt_events);
+            self.emu.set_halt_hook(Box::new(move |reason, ip| {
+                if let Ok(mut events) = events.lock() {
+                    events.push((reason, ip));
+                }
+            }));
+        } else {
+            self.emu.clear_halt_hook();
+        }
+    }
+    #[func]
     fn step(&mut self) -> bool {
-        match self.emu.step() {
+        if self.refuse_while_running("step") {
+            return false;
+        }
+        let before = self.notify_register_changes.then(|| self.emu.registers());
+        let result = self.emu.step();
+        self.flush_halt_events();
+        if let Some(before) = before {
+            self.emit_register_changes(&before);
+        }
+        match result {
             StepResult::Continue => true,
+            StepResult::Breakpoint => {
+                let ip = self.emu.get_ip() as i64;
+                self.signals().breakpoint_hit().emit(ip);
+                false
+            }
             StepResult::Halt => {
+                self.emit_halted();
                 //godot_print!("Resetting...");
                 //self.reset();
                 false
             }
         }
     }
+    // Steps run; negated if the program halted.
+    #[func]
+    fn run(&mut self, max_steps: i64) -> i64 {
+        if self.refuse_while_running("run") {
+            return 0;
+        }
+        let (result, steps) = self.emu.run(max_steps.max(0) as u64);
+        self.flush_halt_events();
+        match result {
+            StepResult::Halt => {
+                self.emit_halted();
+                -(steps as i64)
+            }
+            StepResult::Breakpoint => {
+                let ip = self.emu.get_ip() as i64;
+                self.signals().breakpoint_hit().emit(ip);
+                steps as i64
+            }
+            StepResult::Continue => steps as i64,
+        }
+    }
+    // Runs on a worker thread until halt, a breakpoint, `max_steps`, or
+    // `timeout_ms` (0 = no limit); `run_finished` reports which. Until then
+    // getters read a snapshot from the start of the run, and calls that would
+    // change the emulator (load, reset, step, set_*, write_memory, ...) are
+    // refused with an error, since the worker's result replaces the snapshot.
+    // Returns false if a run is already in flight.
+    #[func]
+    fn run_async(&mut self, max_steps: i64, timeout_ms: i64) -> bool {
+        if self.worker.is_some() {
+            return false;
+        }
+        let snapshot = self.emu.fork();
+        let mut emu = std::mem::replace(&mut self.emu, snapshot);
+        let max_steps = max_steps.max(0) as u64;
+        let timeout = (timeout_ms > 0).then(|| Duration::from_millis(timeout_ms as u64));
+        self.worker = Some(thread::spawn(move || {
+            let (outcome, steps) = emu.run_with_timeout(max_steps, timeout);
+            (emu, outcome, steps)
+        }));
+        true
+    }
+    // True from `run_async` until its `run_finished` signal.
+    #[func]
+    fn is_running(&self) -> bool {
+        self.worker.is_some()
+    }
+    #[func]
+    fn run_until_step(&mut self, n: i64) -> bool {
+        if self.refuse_while_running("run_until_step") {
+            return false;
+        }
+        let halted = self.emu.run_until_step(n.max(0) as u64);
+        self.flush_halt_events();
+        if halted {
+            self.emit_halted();
+        }
+        halted
+    }
+    // Guarded run for programs that may never halt: false once `max_steps`
+    // have run without a halt.
+    #[func]
+    fn run_until_halt(&mut self, max_steps: i64) -> bool {
+        if self.refuse_while_running("run_until_halt") {
+            return false;
+        }
+        let halted = self.emu.run_until_halt(max_steps.max(0) as u64);
+        self.flush_halt_events();
+        if halted {
+            self.emit_halted();
+        }
+        halted
+    }
+    #[func]
+    fn seed_rng(&mut self, seed: i64) {
+        if self.refuse_while_running("seed_rng") {
+            return;
+        }
+        self.emu.seed_rng(seed as u32);
+    }
+    #[func]
+    fn seed_from_time(&mut self) {
+        if self.refuse_while_running("seed_from_time") {
+            return;
+        }
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let salt = (self.base().instance_id().to_i64() as u64)
+            ^ SEED_CALLS.fetch_add(1, Ordering::Relaxed).rotate_left(32);
+        self.emu.seed_rng(time_seed(nanos, salt));
+    }
+    #[func]
+    fn set_trap_uninitialized(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_trap_uninitialized") {
+            return;
+        }
+        self.emu.set_trap_uninitialized(enabled);
+    }
+    // Max SO in bytes; a negative limit restores the SS..MS default.
+    #[func]
+    fn set_stack_limit(&mut self, limit: i64) {
+        if self.refuse_while_running("set_stack_limit") {
+            return;
+        }
+        if limit < 0 {
+            self.emu.clear_stack_limit();
+        } else {
+            self.emu.set_stack_limit(limit.min(0xFFFF) as u16);
+        }
+    }
+    #[func]
+    fn set_mmio_range(&mut self, start: i64, len: i64) {
+        if self.refuse_while_running("set_mmio_range") {
+            return;
+        }
+        self.emu
+            .set_mmio_range(start as u16, len.clamp(0, 0xFFFF) as u16);
+    }
+    // MMIO writes since the last drain, oldest first, as {addr, value}.
+    #[func]
+    fn drain_io(&mut self) -> Array<Dictionary> {
+        if self.refuse_while_running("drain_io") {
+            return Array::new();
+        }
+        let mut events = Array::new();
+        for (addr, value) in self.emu.drain_mmio_events() {
+            let mut dict = Dictionary::new();
+            dict.set("addr", addr as i64);
+            dict.set("value", value as i64);
+            events.push(&dict);
+        }
+        events
+    }
+    #[func]
+    fn get_halt_reason(&self) -> String {
+        self.emu
+            .halt_reason()
+            .map(|reason| reason.as_str().to_string())
+            .unwrap_or_default()
+    }
+    #[func]
+    fn set_strict(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_strict") {
+            return;
+        }
+        self.emu.set_strict(enabled);
+    }
+    #[func]
+    fn get_strict_violation(&self) -> String {
+        self.emu.strict_violation().unwrap_or_default().to_string()
+    }
+    #[func]
+    fn set_debug_mode(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_debug_mode") {
+            return;
+        }
+        self.emu.set_debug_mode(enabled);
+    }
+    #[func]
+    fn get_debug_snapshot(&self) -> PackedInt32Array {
+        self.emu
+            .debug_snapshot()
+            .map(|regs| regs.iter().map(|&r| r as i32).collect())
+            .unwrap_or_default()
+    }
+    // Stack words, bottom first (the last entry is what pop would return).
+    #[func]
+    fn get_stack(&self) -> PackedInt32Array {
+        self.emu
+            .stack_contents()
+            .into_iter()
+            .map(|word| word as i32)
+            .collect()
+    }
+    #[func]
+    fn predict_next_ip(&self) -> i64 {
+        self.emu.predict_next_ip() as i64
+    }
+    #[func]
+    fn disassemble(&self, addr: i64) -> String {
+        self.emu.disassemble_at(addr.max(0) as usize)
+    }
+    // Instruction-indexed listing from IP `start`, up to `count` lines or a halt.
+    #[func]
+    fn disassemble_program(&self, start: i64, count: i64) -> String {
+        self.emu
+            .disassemble_program(start.clamp(0, 0xFFFF) as u16, count.clamp(0, 0xFFFF) as u16)
+    }
+    #[func]
+    fn get_memory_footprint(&self) -> Dictionary {
+        let footprint = self.emu.memory_footprint();
+        let peak = |addr: Option<usize>| addr.map_or(-1, |a| a as i64);
+
+        let mut dict = Dictionary::new();
+        dict.set("code_end", footprint.code_end as i64);
+        dict.set("stack_peak", peak(footprint.stack_peak));
+        dict.set("data_peak", peak(footprint.data_peak));
+        dict
+    }
+    #[func]
+    fn get_register(&self, name: String) -> i64 {
+        register_index(&name).map_or(-1, |idx| self.emu.get_register(idx) as i64)
+    }
+    // Overwrites I; see push_input to queue several values instead.
+    #[func]
+    fn set_input(&mut self, value: i64) {
+        if self.refuse_while_running("set_input") {
+            return;
+        }
+        self.emu.set_input(value as u16);
+    }
+    // Queued values are latched into I one per instruction that reads it;
+    // I keeps its last value once the queue is empty.
+    #[func]
+    fn push_input(&mut self, value: i64) {
+        if self.refuse_while_running("push_input") {
+            return;
+        }
+        self.emu.push_input(value as u16);
+    }
+    // IP counts instructions, not bytes: instruction n starts at byte n * 8.
+    #[func]
+    fn get_ip(&self) -> i64 {
+        self.emu.get_ip() as i64
+    }
+    // Instruction index, clamped to 0..=65535; the next step runs byte ip * 8.
+    #[func]
+    fn set_ip(&mut self, ip: i64) {
+        if self.refuse_while_running("set_ip") {
+            return;
+        }
+        self.emu.set_ip(ip.clamp(0, u16::MAX as i64) as u16);
+    }
+    #[func]
+    fn set_register(&mut self, name: String, value: i64) {
+        if self.refuse_while_running("set_register") {
+            return;
+        }
+        if let Some(idx) = register_index(&name) {
+            self.emu.set_register(idx, value as u16);
+        }
+    }
+    #[func]
+    fn read_memory(&self, addr: i64, len: i64) -> PackedByteArray {
+        let ram = self.emu.ram_slice();
+        let start = (addr.max(0) as usize).min(ram.len());
+        let end = start.saturating_add(len.max(0) as usize).min(ram.len());
+        PackedByteArray::from(&ram[start..end])
+    }
+    #[func]
+    fn write_memory(&mut self, addr: i64, bytes: PackedByteArray) {
+        if self.refuse_while_running("write_memory") {
+            return;
+        }
+        self.emu.write_ram(addr.max(0) as usize, bytes.as_slice());
+    }
+    #[func]
+    fn snapshot(&self) -> PackedByteArray {
+        PackedByteArray::from(self.emu.snapshot())
+    }
+    #[func]
+    fn restore(&mut self, data: PackedByteArray) -> bool {
+        if self.refuse_while_running("restore") {
+            return false;
+        }
+        self.emu.restore(data.as_slice())
+    }
+    #[func]
+    fn set_trace_enabled(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_trace_enabled") {
+            return;
+        }
+        self.emu.set_trace_enabled(enabled);
+    }
+    #[func]
+    fn set_trace_capacity(&mut self, capacity: i64) {
+        if self.refuse_while_running("set_trace_capacity") {
+            return;
+        }
+        self.emu.set_trace_capacity(capacity.max(0) as usize);
+    }
+    #[func]
+    fn set_profiling(&mut self, enabled: bool) {
+        if self.refuse_while_running("set_profiling") {
+            return;
+        }
+        self.emu.set_profiling(enabled);
+    }
+    // mnemonic -> executions since reset, for opcodes that ran while profiling.
+    #[func]
+    fn get_profile(&self) -> Dictionary {
+        let mut dict = Dictionary::new();
+        for (name, count) in self.emu.get_opcode_counts() {
+            dict.set(name, count as i64);
+        }
+        dict
+    }
+    #[func]
+    fn get_trace(&self) -> Array<Dictionary> {
+        let mut trace = Array::new();
+        for (ip, opcode) in self.emu.get_trace() {
+            let mut entry = Dictionary::new();
+            entry.set("ip", ip as i64);
+            entry.set("opcode", opcode as i64);
+            trace.push(&entry);
+        }
+        trace
+    }
+    // Signed mode is bit 0 of O; these keep the two in sync.
+    #[func]
+    fn set_signed(&mut self, signed: bool) {
+        if self.refuse_while_running("set_signed") {
+            return;
+        }
+        self.emu.set_signed(signed);
+    }
+    #[func]
+    fn is_signed(&self) -> bool {
+        self.emu.is_signed()
+    }
+    #[func]
+    fn get_state_dict(&self) -> Dictionary {
+        let mut dict = Dictionary::new();
+        for (name, val) in REGISTER_NAMES.iter().zip(self.emu.registers()) {
+            dict.set(*name, val as i64);
+        }
+        dict.set("cycles", self.emu.get_cycles() as i64);
+        dict.set("signed", self.emu.is_signed());
+        dict
+    }
+    // Same register data as get_state_dict, formatted for printing.
     #[func]
     fn print_state(&mut self) -> String {
-        return self.emu.get_state_string();
+        self.emu.get_state_string()
+    }
+    #[func]
+    fn get_cycles(&self) -> i64 {
+        self.emu.get_cycles() as i64
     }
     #[func]
     fn benchmark(&mut self, steps: i32) -> f64 {
-        let start = Instant::now();
-        for _ in 0..steps {
-            self.emu.step();
+        if self.refuse_while_running("benchmark") {
+            return 0.0;
         }
+        // count what actually ran; a halting program stops short of `steps`
+        let before = self.emu.get_cycles();
+        let start = Instant::now();
+        self.emu.run_until_step(before + steps.max(0) as u64);
         let elapsed = start.elapsed().as_secs_f64();
-        steps as f64 / elapsed
+        (self.emu.get_cycles() - before) as f64 / elapsed
+    }
+    #[func]
+    fn precompile(&mut self) {
+        if self.refuse_while_running("precompile") {
+            return;
+        }
+        self.emu.precompile();
     }
     #[func]
     fn benchmark_multi(&mut self, program: PackedByteArray, iterations: i32, n_tests: i32) -> f64 {
-        // Convert PackedByteArray to Vec<u16> like in load_program
-        let program_vec: Vec<u16> = program
-            .as_slice()
-            .chunks_exact(2)
-            .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
-            .collect();
+        if self.refuse_while_running("benchmark_multi") {
+            return 0.0;
+        }
+        self.benchmark_runs(&to_words(program.as_slice()), iterations, n_tests, false)
+    }
+    // Same as benchmark_multi, but each run decodes the program up front.
+    #[func]
+    fn benchmark_multi_precompiled(
+        &mut self,
+        program: PackedByteArray,
+        iterations: i32,
+        n_tests: i32,
+    ) -> f64 {
+        if self.refuse_while_running("benchmark_multi_precompiled") {
+            return 0.0;
+        }
+        self.benchmark_runs(&to_words(program.as_slice()), iterations, n_tests, true)
+    }
+}
+
+impl EmulatorNode {
+    // While `run_async` is in flight a change to the snapshot would be lost
+    // when the worker's emulator comes back, so such calls are refused.
+    fn refuse_while_running(&self, call: &str) -> bool {
+        if self.worker.is_none() {
+            return false;
+        }
+        godot_error!("{}: refused while run_async is in flight", call);
+        true
+    }
+
+    // IP moves on every step, so it is left out to keep the signal meaningful.
+    fn emit_register_changes(&mut self, before: &[u16; NUM_REGS]) {
+        let after = self.emu.registers();
+        for (idx, name) in REGISTER_NAMES.iter().enumerate() {
+            if idx != REG_IP && before[idx] != after[idx] {
+                self.signals().register_changed().emit(
+                    *name,
+                    before[idx] as i64,
+                    after[idx] as i64,
+                );
+            }
+        }
+    }
+
+    fn emit_halted(&mut self) {
+        let ip = self.emu.halt_ip().unwrap_or(self.emu.get_ip()) as i64;
+        self.signals().halted().emit(ip);
+    }
+
+    fn flush_halt_events(&mut self) {
+        let events = match self.halt_events.lock() {
+            Ok(mut events) if !events.is_empty() => std::mem::take(&mut *events),
+            _ => return,
+        };
+        for (reason, ip) in events {
+            self.signals()
+                .halt_logged()
+                .emit(reason.as_str(), ip as i64);
+        }
+    }
 
+    fn benchmark_runs(
+        &mut self,
+        program: &[u16],
+        iterations: i32,
+        n_tests: i32,
+        precompile: bool,
+    ) -> f64 {
         let mut total_time = 0.0;
+        let mut total_steps = 0;
 
         for _ in 0..n_tests {
             self.emu.reset();
-            self.emu.load_program(&program_vec);
+            self.emu.load_program_at_lossy(program, 0);
+            if precompile {
+                self.emu.precompile();
+            }
 
             let start = Instant::now();
-            for _ in 0..iterations {
-                self.emu.step();
-            }
+            self.emu.run_until_step(iterations.max(0) as u64);
             let elapsed = start.elapsed().as_secs_f64();
             total_time += elapsed;
+            total_steps += self.emu.get_cycles();
         }
 
-        let avg_time = total_time / n_tests as f64;
-        iterations as f64 / avg_time
+        total_steps as f64 / total_time
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neozasm::assemble;
+
+    #[test]
+    fn fixed_seed_replays_rand() {
+        let program = assemble("rand A\nrand B\nrand C\n").unwrap();
+        let draw = |seed: u32| {
+            let mut emu = emu_module::Emulator::new();
+            emu.seed_rng(seed);
+            emu.load_program(&program).unwrap();
+            emu.run(10);
+            [0, 1, 2].map(|reg| emu.get_register(reg))
+        };
+        assert_eq!(draw(42), draw(42));
+        assert_ne!(draw(42), draw(43));
+        // same clock reading, different nodes
+        assert_ne!(time_seed(123_456_789, 1), time_seed(123_456_789, 2));
+    }
+
+    #[test]
+    fn to_words_reads_little_endian_pairs() {
+        assert_eq!(to_words(&[0x34, 0x12, 0xCD, 0xAB, 0xFF]), [0x1234, 0xABCD]);
+
+        // bytes laid out like AssemblrNode.assemble's output load and run
+        let bytes: Vec<u8> = assemble("mov 7, A\nadd A, 5, B\n")
+            .unwrap()
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+        let mut emu = emu_module::Emulator::new();
+        emu.load_program(&to_words(&bytes)).unwrap();
+        emu.run(10);
+        assert_eq!(emu.get_register(emu_module::REG_B), 12);
     }
 }
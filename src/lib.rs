@@ -1,9 +1,14 @@
 pub mod emulator;
+#[cfg(feature = "godot")]
 pub(crate) mod gdassembler;
+#[cfg(feature = "godot")]
 pub mod gdemulator;
+#[cfg(feature = "godot")]
 use godot::prelude::*;
 pub mod neozasm;
+#[cfg(feature = "godot")]
 struct CrustZinc;
 
+#[cfg(feature = "godot")]
 #[gdextension]
 unsafe impl ExtensionLibrary for CrustZinc {}
@@ -1,72 +1,449 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
+use crate::emulator::{MEM_SIZE, REGISTER_NAMES, register_index};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub line: usize,
+    pub message: String,
+}
+
+// First error-severity diagnostic of a failed `assemble`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} on line {}", self.message, self.line)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Label,
+    Const,
+    // `alias NAME: REG`; the symbol's value is the register index
+    Alias,
+}
+
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub value: u16,
+    pub kind: SymbolKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum RoundtripError {
+    // the source itself does not assemble
+    Assemble(Diagnostic),
+    // the disassembly of the output does not assemble
+    Reassemble(Diagnostic),
+    // first instruction slot whose words differ after the round trip
+    Mismatch {
+        ip: usize,
+        original: [u16; 4],
+        reassembled: [u16; 4],
+    },
+}
+
+enum Line {
+    Instruction(String),
+    Raw(Vec<u16>),
+}
+
+#[derive(Clone)]
+enum DataItem {
+    Bytes(Vec<u8>),
+    Expr(String),
+}
+
+// One open `.if`; lines are kept while every open frame is on its live branch.
+struct CondFrame {
+    line: usize,
+    taken: bool,
+    in_else: bool,
+}
+
+// (source line, IP) for every line that emitted words
+pub type SourceMap = Vec<(usize, u16)>;
+
+struct Assembled {
+    words: Vec<u16>,
+    diagnostics: Vec<Diagnostic>,
+    symbols: Vec<Symbol>,
+    source_map: SourceMap,
+}
+
+const OPCODES: &[(&str, u16)] = &[
+    ("mov", 1),
+    ("add", 2),
+    ("sub", 3),
+    ("mul", 4),
+    ("and", 5),
+    ("or", 6),
+    ("xor", 7),
+    ("not", 8),
+    ("jmp", 9),
+    ("jml", 10),
+    ("jmle", 11),
+    ("jmb", 12),
+    ("jmbe", 13),
+    ("jme", 14),
+    ("jmne", 15),
+    ("save", 16),
+    ("load", 17),
+    ("push", 18),
+    ("pop", 19),
+    ("halt", 20),
+    ("shl", 21),
+    ("shr", 22),
+    ("loada", 23),
+    ("rand", 24),
+    ("div", 25),
+    ("mod", 26),
+    ("mac", 27),
+    ("dbgbreak", 28),
+    ("peek", 29),
+    ("andn", 30),
+    ("ptrdiff", 31),
+    ("setlt", 32),
+    ("sete", 33),
+    ("setgt", 34),
+    ("rcl", 35),
+    ("rcr", 36),
+    ("cmp", 37),
+    ("jz", 38),
+    ("jnz", 39),
+    ("jg", 40),
+    ("jl", 41),
+    ("jge", 42),
+    ("jle", 43),
+    ("call", 44),
+    ("ret", 45),
+    ("inc", 46),
+    ("dec", 47),
+    ("rol", 48),
+    ("ror", 49),
+    ("nop", 50),
+    ("loadb", 51),
+    ("saveb", 52),
+    ("test", 53),
+    ("bset", 54),
+    ("bclr", 55),
+    ("btst", 56),
+    ("copy", 57),
+    ("fill", 58),
+];
+
+pub fn mnemonic(opcode: u16) -> Option<&'static str> {
+    OPCODES
+        .iter()
+        .find(|(_, num)| num - 1 == opcode)
+        .map(|(name, _)| *name)
+}
+
+// Register names are case-insensitive.
 fn reg_index(s: &str) -> Option<u16> {
-    match s {
-        "A" => Some(0),
-        "B" => Some(1),
-        "C" => Some(2),
-        "D" => Some(3),
-        "IP" => Some(4),
-        "SS" => Some(5),
-        "SO" => Some(6),
-        "MS" => Some(7),
-        "MO" => Some(8),
-        "I" => Some(9),
-        "O" => Some(10),
-        "ST" => Some(11),
-        _ => None,
+    register_index(s)
+        .or_else(|| register_index(&s.to_ascii_uppercase()))
+        .map(|idx| idx as u16)
+}
+
+// Register named by an operand, directly or through an `alias`. A symbol
+// spelled exactly like a register wins over a case-folded match, so labels
+// such as `a` or `st` keep meaning the label.
+fn operand_reg(
+    s: &str,
+    symbols: &HashMap<String, u16>,
+    aliases: &HashMap<String, u16>,
+) -> Option<u16> {
+    if let Some(&reg) = aliases.get(s) {
+        return Some(reg);
+    }
+    if register_index(s).is_none() && symbols.contains_key(s) {
+        return None;
+    }
+    reg_index(s)
+}
+
+// Splits on `delim`, except inside '...' or "..." (backslash escapes the next char).
+fn split_unquoted(s: &str, delim: char) -> Vec<&str> {
+    let mut pieces = vec![];
+    let mut start = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == delim => {
+                pieces.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            None => {}
+        }
+    }
+    pieces.push(&s[start..]);
+    pieces
+}
+
+// A lone `'c'` literal, with the escapes \n \t \0 \\ and \'. Ok(None) when `s`
+// isn't one, e.g. `'a' + 1`, which resolve_expr splits first.
+fn parse_char(s: &str) -> Result<Option<u16>, String> {
+    let Some(rest) = s.strip_prefix('\'') else {
+        return Ok(None);
+    };
+
+    let mut close = None;
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '\'' => {
+                close = Some(i);
+                break;
+            }
+            _ => {}
+        }
     }
+    let Some(close) = close else {
+        return Err(format!("Unterminated char literal {}", s));
+    };
+    if close + 1 != rest.len() {
+        return Ok(None);
+    }
+
+    let body = unescape(&rest[..close])?;
+    let mut body_chars = body.chars();
+    match (body_chars.next(), body_chars.next()) {
+        // source is ASCII-only (checked per line), so this never truncates
+        (Some(c), None) => Ok(Some(c as u16)),
+        _ => Err(format!("Invalid char literal {}", s)),
+    }
+}
+
+// Body of a char or string literal with \n \t \0 \\ \' and \" resolved.
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(body.len());
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        out.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '\'' | '"')) => c,
+            Some(c) => return Err(format!("Unknown escape '\\{}'", c)),
+            None => return Err("Dangling '\\' at end of literal".to_string()),
+        });
+    }
+    Ok(out)
 }
 
-fn resolve_expr(s: &str, symbols: &HashMap<String, u16>) -> u16 {
+// Numeric literal: decimal, `0x` hex, `0b` binary (either prefix case) or a
+// char literal. Ok(None) means "not a number", e.g. a symbol or an expression.
+fn parse_number(literal: &str) -> Result<Option<u16>, String> {
+    if let Some(value) = parse_char(literal)? {
+        return Ok(Some(value));
+    }
+
+    // `1_000` / `0b1010_1010`: separators are dropped from anything that starts
+    // like a number; identifiers such as `sp_label` are left alone.
+    let unsigned = literal.strip_prefix('-').unwrap_or(literal);
+    let cleaned;
+    let s = if literal.contains('_')
+        && unsigned.starts_with(|c: char| c.is_ascii_digit())
+        && unsigned
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_')
+    {
+        if literal.ends_with('_') || literal.contains("__") {
+            return Err(format!("Misplaced '_' in literal '{}'", literal));
+        }
+        cleaned = literal.replace('_', "");
+        cleaned.as_str()
+    } else {
+        literal
+    };
+
+    if let Some(digits) = s.strip_prefix('-')
+        && !digits.is_empty()
+        && digits.bytes().all(|b| b.is_ascii_digit())
+    {
+        // two's complement, so -1 is 0xFFFF
+        return s
+            .parse::<i16>()
+            .map(|n| Some(n as u16))
+            .map_err(|_| format!("Literal '{}' does not fit in 16 bits", literal));
+    }
+    if !s.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return Ok(None);
+    }
+
+    let prefixed = [("0x", 16, "hex"), ("0b", 2, "binary")]
+        .into_iter()
+        .find_map(|(prefix, radix, kind)| {
+            let head = s.get(..2)?;
+            head.eq_ignore_ascii_case(prefix)
+                .then(|| (&s[2..], radix, kind))
+        });
+    if let Some((digits, radix, kind)) = prefixed {
+        if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+            return Err(format!("Invalid {} literal '{}'", kind, literal));
+        }
+        return u16::from_str_radix(digits, radix)
+            .map(Some)
+            .map_err(|_| format!("Literal '{}' does not fit in 16 bits", literal));
+    }
+    if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+        return s
+            .parse::<u16>()
+            .map(Some)
+            .map_err(|_| format!("Literal '{}' does not fit in 16 bits", literal));
+    }
+    Ok(None)
+}
+
+// Rightmost binary operator from `ops` at paren depth 0 and outside quotes, so
+// chains like `8 - 2 - 1` split as `(8 - 2) - 1`. A `-` or `+` with no operand
+// before it (start, after another operator, or after `(`) is a sign, not a split.
+fn find_operator(s: &str, ops: &[char]) -> Option<usize> {
+    let mut found = None;
+    let mut depth = 0i32;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev: Option<char> = None;
+    for (i, c) in s.char_indices() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None => match c {
+                '\'' | '"' => quote = Some(c),
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ if depth == 0 && ops.contains(&c) => {
+                    let binary = prev.is_some_and(|p| !"+-*/(".contains(p));
+                    if binary {
+                        found = Some(i);
+                    }
+                }
+                _ => {}
+            },
+        }
+        if !c.is_whitespace() {
+            prev = Some(c);
+        }
+    }
+    found
+}
+
+// Constant expression: numbers, symbols, + - * / (usual precedence, wrapping
+// u16 arithmetic), parentheses and unary minus. A bare register name resolves
+// to its index.
+fn resolve_expr(s: &str, symbols: &HashMap<String, u16>) -> Result<u16, String> {
     let s = s.trim();
 
-    if let Ok(n) = s.parse::<u16>() {
-        return n;
+    if let Some(n) = parse_number(s)? {
+        return Ok(n);
     }
 
     if let Some(&val) = symbols.get(s) {
-        return val;
+        return Ok(val);
     }
 
-    if let Some((lhs, rhs)) = s.split_once('+') {
-        return resolve_expr(lhs.trim(), symbols) + resolve_expr(rhs.trim(), symbols);
+    for ops in [['+', '-'], ['*', '/']] {
+        let Some(idx) = find_operator(s, &ops) else {
+            continue;
+        };
+        let lhs = resolve_expr(&s[..idx], symbols)?;
+        let rhs = resolve_expr(&s[idx + 1..], symbols)?;
+        return match &s[idx..idx + 1] {
+            "+" => Ok(lhs.wrapping_add(rhs)),
+            "-" => Ok(lhs.wrapping_sub(rhs)),
+            "*" => Ok(lhs.wrapping_mul(rhs)),
+            _ => lhs
+                .checked_div(rhs)
+                .ok_or_else(|| format!("Division by zero in '{}'", s)),
+        };
     }
 
-    if let Some((lhs, rhs)) = s.split_once('-') {
-        return resolve_expr(lhs.trim(), symbols).wrapping_sub(resolve_expr(rhs.trim(), symbols));
+    if let Some(inner) = s.strip_prefix('(').and_then(|rest| rest.strip_suffix(')')) {
+        return resolve_expr(inner, symbols);
+    }
+
+    if let Some(operand) = s.strip_prefix('-') {
+        return Ok(resolve_expr(operand, symbols)?.wrapping_neg());
     }
 
     if let Some(reg) = reg_index(s) {
-        return reg;
+        return Ok(reg);
     }
 
-    panic!("Invalid operand '{}'", s);
+    Err(format!("Invalid operand '{}'", s))
 }
 
-fn resolve_operand(s: &str, symbols: &HashMap<String, u16>) -> (u16, bool) {
+fn resolve_operand(
+    s: &str,
+    symbols: &HashMap<String, u16>,
+    aliases: &HashMap<String, u16>,
+    warnings: &mut Vec<String>,
+) -> Result<(u16, bool), String> {
     let s = s.trim();
 
-    if let Ok(n) = s.parse::<u16>() {
-        return (n, true);
+    if let Some(n) = parse_number(s)? {
+        return Ok((n, true));
     }
 
     if let Some((lhs, rhs)) = s.split_once('+') {
         let lhs_trim = lhs.trim();
         let rhs_trim = rhs.trim();
 
-        if let Some(reg) = reg_index(lhs_trim) {
-            let offset = resolve_expr(rhs_trim, symbols);
+        let reg_offset = if let Some(reg) = operand_reg(lhs_trim, symbols, aliases) {
+            Some((reg, lhs_trim, resolve_expr(rhs_trim, symbols)?))
+        } else if let Some(reg) = operand_reg(rhs_trim, symbols, aliases) {
+            Some((reg, rhs_trim, resolve_expr(lhs_trim, symbols)?))
+        } else {
+            None
+        };
+
+        if let Some((reg, reg_name, offset)) = reg_offset {
             if offset > 15 {
-                panic!("Offset too large (max 15): {}", offset);
+                return Err(format!("Offset too large (max 15): {}", offset));
             }
-            return ((offset << 12) | reg, false);
-        } else if let Some(reg) = reg_index(rhs_trim) {
-            let offset = resolve_expr(lhs_trim, symbols);
-            if offset > 15 {
-                panic!("Offset too large (max 15): {}", offset);
+            if offset > 8 {
+                warnings.push(format!(
+                    "Offset +{} on {} is decoded as -{}",
+                    offset,
+                    reg_name,
+                    16 - offset
+                ));
             }
-            return ((offset << 12) | reg, false);
+            return Ok(((offset << 12) | reg, false));
         }
     }
 
@@ -74,77 +451,704 @@ fn resolve_operand(s: &str, symbols: &HashMap<String, u16>) -> (u16, bool) {
         let lhs_trim = lhs.trim();
         let rhs_trim = rhs.trim();
 
-        if let Some(reg) = reg_index(lhs_trim) {
-            let offset = resolve_expr(rhs_trim, symbols);
+        let reg_offset = if let Some(reg) = operand_reg(lhs_trim, symbols, aliases) {
+            Some((reg, lhs_trim, resolve_expr(rhs_trim, symbols)?))
+        } else if let Some(reg) = operand_reg(rhs_trim, symbols, aliases) {
+            Some((reg, rhs_trim, resolve_expr(lhs_trim, symbols)?))
+        } else {
+            None
+        };
+
+        if let Some((reg, reg_name, offset)) = reg_offset {
             if offset > 15 {
-                panic!("Offset too large (max 15): {}", offset);
+                return Err(format!("Offset too large (max 15): {}", offset));
             }
-            let encoded = ((16 - offset) << 12) | reg;
-            return (encoded, false);
-        } else if let Some(reg) = reg_index(rhs_trim) {
-            let offset = resolve_expr(lhs_trim, symbols);
-            if offset > 15 {
-                panic!("Offset too large (max 15): {}", offset);
+            if offset == 8 {
+                warnings.push(format!("Offset -8 on {} is decoded as +8", reg_name));
             }
             let encoded = ((16 - offset) << 12) | reg;
-            return (encoded, false);
+            return Ok((encoded, false));
         }
     }
 
-    if let Some(reg) = reg_index(s) {
-        return (reg, false);
-    }
-
-    if symbols.contains_key(s) || s.contains('+') || s.contains('-') {
-        return (resolve_expr(s, symbols), true);
-    }
-
-    panic!("Invalid operand '{}'", s);
-}
-
-pub fn assemble(source: &str) -> Vec<u16> {
-    let opcodes = HashMap::from([
-        ("mov", 1),
-        ("add", 2),
-        ("sub", 3),
-        ("mul", 4),
-        ("and", 5),
-        ("or", 6),
-        ("xor", 7),
-        ("not", 8),
-        ("jmp", 9),
-        ("jml", 10),
-        ("jmle", 11),
-        ("jmb", 12),
-        ("jmbe", 13),
-        ("jme", 14),
-        ("jmne", 15),
-        ("save", 16),
-        ("load", 17),
-        ("push", 18),
-        ("pop", 19),
-        ("halt", 20),
-        ("shl", 21),
-        ("shr", 22),
-    ]);
+    if let Some(reg) = operand_reg(s, symbols, aliases) {
+        return Ok((reg, false));
+    }
+
+    if symbols.contains_key(s) || s.contains(['+', '-', '*', '/', '(']) {
+        return Ok((resolve_expr(s, symbols)?, true));
+    }
+
+    Err(format!("Invalid operand '{}'", s))
+}
+
+fn expect_args(name: &str, args: &[String], count: usize) -> Result<(), String> {
+    if args.len() != count {
+        return Err(format!(
+            "'{}' expects {} operand(s), got {}",
+            name,
+            count,
+            args.len()
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Operand {
+    // value operand; its immediate flag bit is the slot index
+    Src(usize),
+    // like Src, but holds a code address (disassembly may show a label)
+    Target(usize),
+    // register written by the instruction; never flagged immediate
+    Dst(usize),
+}
+
+use Operand::{Dst, Src, Target};
+
+// Source-order operands of each mnemonic and the a/b/c slot (0/1/2) each one lands in.
+fn operand_layout(name: &str) -> Option<&'static [Operand]> {
+    let layout: &[Operand] = match name {
+        "mov" => &[Src(0), Dst(1)],
+        "add" | "sub" | "and" | "andn" | "or" | "xor" | "shl" | "shr" | "rol" | "ror" | "mod" => {
+            &[Src(0), Src(1), Dst(2)]
+        }
+        "mul" | "div" | "mac" => &[Src(0), Src(1)],
+        "not" => &[Src(0), Dst(1)],
+        "ptrdiff" | "setlt" | "sete" | "setgt" => &[Dst(2), Src(0), Src(1)],
+        "jmp" | "jz" | "jnz" | "jg" | "jl" | "jge" | "jle" | "call" => &[Target(2)],
+        "cmp" | "test" | "btst" => &[Src(0), Src(1)],
+        "bset" | "bclr" => &[Dst(0), Src(1)],
+        "jml" | "jmle" | "jmb" | "jmbe" | "jme" | "jmne" => &[Src(0), Src(1), Target(2)],
+        // save(dest_addr_ptr, src_value): the value is stored from 'a', the address from 'b'
+        "save" | "saveb" => &[Src(1), Src(0)],
+        // load(dest_reg, src_addr_ptr) / loada(dest_reg, addr): 'b' = address, 'c' = dest_reg
+        "load" | "loada" | "loadb" => &[Dst(2), Src(1)],
+        // copy(src_addr, dest_addr, len_words)
+        // fill(dest_addr, value, len_words)
+        "copy" | "fill" => &[Src(0), Src(1), Src(2)],
+        "push" => &[Src(0)],
+        "pop" | "rand" | "rcl" | "rcr" | "inc" | "dec" => &[Dst(0)],
+        "peek" => &[Dst(0), Src(1)],
+        "halt" | "dbgbreak" | "ret" | "nop" => &[],
+        _ => return None,
+    };
+    Some(layout)
+}
+
+fn assemble_instruction(
+    name: &str,
+    args: &[String],
+    labels: &HashMap<String, u16>,
+    aliases: &HashMap<String, u16>,
+    warnings: &mut Vec<String>,
+) -> Result<[u16; 4], String> {
+    let layout = operand_layout(name).ok_or_else(|| format!("Unknown instruction '{}'", name))?;
+    expect_args(name, args, layout.len())?;
+
+    let mut slots = [0u16; 3];
+    let mut f = 0;
+    for (arg, operand) in args.iter().zip(layout) {
+        let (value, immediate) = resolve_operand(arg, labels, aliases, warnings)?;
+        match *operand {
+            Src(slot) | Target(slot) => {
+                slots[slot] = value;
+                if immediate {
+                    f |= 1 << slot;
+                }
+            }
+            Dst(slot) => slots[slot] = value,
+        }
+    }
+
+    Ok([f, slots[0], slots[1], slots[2]])
+}
+
+fn disassemble_operand(
+    value: u16,
+    immediate: bool,
+    target: bool,
+    symbols: Option<&HashMap<String, u16>>,
+) -> String {
+    if immediate {
+        if target {
+            // pick the alphabetically first name so output is stable
+            let name = symbols.and_then(|symbols| {
+                symbols
+                    .iter()
+                    .filter(|(_, v)| **v == value)
+                    .map(|(k, _)| k)
+                    .min()
+            });
+            if let Some(name) = name {
+                return name.clone();
+            }
+        }
+        return value.to_string();
+    }
+
+    let reg = (value & 0x0FFF) as usize;
+    let offset = value >> 12;
+    let Some(reg_name) = REGISTER_NAMES.get(reg) else {
+        return format!("?{}", value);
+    };
+    // mirrors `Emulator::r_i`: offsets above 8 count down from 16
+    match offset {
+        0 => reg_name.to_string(),
+        1..=8 => format!("{}+{}", reg_name, offset),
+        _ => format!("{}-{}", reg_name, 16 - offset),
+    }
+}
+
+pub fn disassemble_instruction(words: [u16; 4], symbols: Option<&HashMap<String, u16>>) -> String {
+    let [header, a, b, c] = words;
+    let f = header >> 13;
+    let opcode = header & 0x1FFF;
+
+    let Some(name) = mnemonic(opcode) else {
+        return format!("??? {:#06X}", header);
+    };
+    let layout = operand_layout(name).unwrap_or(&[]);
+
+    let slots = [a, b, c];
+    let operands: Vec<String> = layout
+        .iter()
+        .map(|operand| match *operand {
+            Src(slot) => disassemble_operand(slots[slot], f & (1 << slot) != 0, false, symbols),
+            Target(slot) => disassemble_operand(slots[slot], f & (1 << slot) != 0, true, symbols),
+            Dst(slot) => disassemble_operand(slots[slot], false, false, symbols),
+        })
+        .collect();
+
+    if operands.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, operands.join(", "))
+    }
+}
+
+pub fn disassemble_listing(program: &[u16], symbols: Option<&HashMap<String, u16>>) -> String {
+    program
+        .chunks(4)
+        .enumerate()
+        .map(|(ip, chunk)| {
+            let mut words = [0u16; 4];
+            words[..chunk.len()].copy_from_slice(chunk);
+            format!("{:04X}: {}", ip, disassemble_instruction(words, symbols))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Decoded program for external tools: one object per instruction slot plus
+// the symbol map (sorted by name so the output is stable).
+pub fn program_to_json(words: &[u16], symbols: Option<&HashMap<String, u16>>) -> String {
+    let instructions: Vec<String> = words
+        .chunks(4)
+        .enumerate()
+        .map(|(ip, chunk)| {
+            let mut slot = [0u16; 4];
+            slot[..chunk.len()].copy_from_slice(chunk);
+            let [header, a, b, c] = slot;
+            let opcode = header & 0x1FFF;
+            let name = mnemonic(opcode).map_or("null".to_string(), json_string);
+            format!(
+                "{{\"ip\":{},\"opcode\":{},\"mnemonic\":{},\"flags\":{},\"operands\":[{},{},{}],\"text\":{}}}",
+                ip,
+                opcode,
+                name,
+                header >> 13,
+                a,
+                b,
+                c,
+                json_string(&disassemble_instruction(slot, symbols))
+            )
+        })
+        .collect();
+
+    let mut names: Vec<(&String, &u16)> = symbols.into_iter().flatten().collect();
+    names.sort();
+    let symbols: Vec<String> = names
+        .into_iter()
+        .map(|(name, value)| format!("{}:{}", json_string(name), value))
+        .collect();
+
+    format!(
+        "{{\"instructions\":[{}],\"symbols\":{{{}}}}}",
+        instructions.join(","),
+        symbols.join(",")
+    )
+}
+
+// `.incbin` payload: hex bytes, packed little-endian into words like the
+// byte arrays handed to the emulator. An odd trailing byte is zero-padded.
+fn parse_incbin(data: &str) -> Result<Vec<u16>, String> {
+    let hex: String = data.split_whitespace().collect();
+    if hex.is_empty() || !hex.len().is_multiple_of(2) || !hex.bytes().all(|b| b.is_ascii_hexdigit())
+    {
+        return Err(format!("Invalid .incbin data '{}'", data.trim()));
+    }
+
+    let bytes: Vec<u8> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect();
+
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+        .collect())
+}
+
+// Items of a `db` (bytes) or `dw` (words) line. Only `db` takes "strings".
+fn parse_data(args: &str, wide: bool) -> Result<Vec<DataItem>, String> {
+    split_unquoted(args, ',')
+        .into_iter()
+        .map(|item| {
+            let item = item.trim();
+            if item.is_empty() {
+                return Err("Empty data item".to_string());
+            }
+            let Some(body) = item.strip_prefix('"') else {
+                return Ok(DataItem::Expr(item.to_string()));
+            };
+            if wide {
+                return Err(format!("Strings are only allowed in db: {}", item));
+            }
+            let body = body
+                .strip_suffix('"')
+                .ok_or_else(|| format!("Unterminated string {}", item))?;
+            Ok(DataItem::Bytes(unescape(body)?.into_bytes()))
+        })
+        .collect()
+}
+
+// Words a data line repeated `repeat` times occupies before slot padding;
+// `db` packs two bytes per word, across repeats too.
+fn data_len(items: &[DataItem], wide: bool, repeat: usize) -> Option<usize> {
+    if wide {
+        return items.len().checked_mul(repeat);
+    }
+    let bytes: usize = items
+        .iter()
+        .map(|item| match item {
+            DataItem::Bytes(bytes) => bytes.len(),
+            DataItem::Expr(_) => 1,
+        })
+        .sum();
+    Some(bytes.checked_mul(repeat)?.div_ceil(2))
+}
+
+fn resolve_data(
+    items: &[DataItem],
+    wide: bool,
+    symbols: &HashMap<String, u16>,
+) -> Result<Vec<u16>, String> {
+    let mut words = vec![];
+    let mut bytes = vec![];
+    for item in items {
+        match item {
+            DataItem::Bytes(data) => bytes.extend_from_slice(data),
+            DataItem::Expr(expr) => {
+                let value = resolve_expr(expr, symbols)?;
+                if wide {
+                    words.push(value);
+                } else {
+                    let byte = u8::try_from(value)
+                        .map_err(|_| format!("Value {} does not fit in a byte", value))?;
+                    bytes.push(byte);
+                }
+            }
+        }
+    }
+    if !wide {
+        // little-endian, like .incbin
+        words = bytes
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]))
+            .collect();
+    }
+    Ok(words)
+}
+
+// Records where `name` is defined; labels and consts share one namespace.
+fn redefinition(defined: &mut HashMap<String, usize>, name: &str, line: usize) -> Option<String> {
+    match defined.get(name) {
+        Some(first) => Some(format!(
+            "'{}' is already defined on line {} (redefined on line {})",
+            name, first, line
+        )),
+        None => {
+            defined.insert(name.to_string(), line);
+            None
+        }
+    }
+}
+
+// Identifiers an expression refers to, skipping quoted char literals.
+fn expr_names(expr: &str) -> Vec<&str> {
+    let mut names = vec![];
+    let mut quote = None;
+    let mut escaped = false;
+    let mut start = None;
+    for (i, c) in expr.char_indices().chain([(expr.len(), ' ')]) {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c.is_ascii_alphanumeric() || c == '_' || c == '.' => {
+                start.get_or_insert(i);
+            }
+            None => {
+                if let Some(from) = start.take() {
+                    names.push(&expr[from..i]);
+                }
+                if c == '\'' || c == '"' {
+                    quote = Some(c);
+                }
+            }
+        }
+    }
+    names
+}
+
+// Second-pass const resolution: consts may name labels and other consts
+// defined anywhere, so keep evaluating until nothing new resolves. A const
+// waits while it names another unresolved const, so `B` in `const A: B + 1`
+// is not mistaken for the register. Returns the label map extended with
+// every const that resolved; the rest (cycles, undefined names, bad
+// arithmetic) are reported on their own lines.
+fn resolve_consts(
+    defs: &[(String, String, usize)],
+    labels: &HashMap<String, u16>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> HashMap<String, u16> {
+    let mut values = labels.clone();
+    let mut pending: Vec<&(String, String, usize)> = defs.iter().collect();
+    let waiting_on = |expr: &str, pending: &[&(String, String, usize)]| {
+        expr_names(expr)
+            .into_iter()
+            .find(|name| pending.iter().any(|(p, _, _)| p == name))
+            .map(str::to_string)
+    };
+    loop {
+        let before = pending.len();
+        let mut next = vec![];
+        for def in &pending {
+            let (name, expr, _) = def;
+            if waiting_on(expr, &pending).is_some() {
+                next.push(*def);
+                continue;
+            }
+            match resolve_expr(expr, &values) {
+                Ok(value) => {
+                    values.insert(name.clone(), value);
+                }
+                Err(_) => next.push(*def),
+            }
+        }
+        pending = next;
+        if pending.is_empty() || pending.len() == before {
+            break;
+        }
+    }
+    for (name, expr, line) in &pending {
+        let message = match waiting_on(expr, &pending) {
+            Some(dep) => format!("depends on unresolved '{}'", dep),
+            None => resolve_expr(expr, &values).unwrap_err(),
+        };
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            line: *line,
+            message: format!("Cannot resolve const '{}': {}", name, message),
+        });
+    }
+    values
+}
+
+// Rewrites local label references (`.loop`) outside quotes to their
+// scoped name (`draw.loop`), matching how local definitions are stored.
+fn qualify_locals(text: &str, scope: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut quote = None;
+    let mut escaped = false;
+    let mut prev: Option<char> = None;
+    for c in text.chars() {
+        match quote {
+            _ if escaped => escaped = false,
+            Some(_) if c == '\\' => escaped = true,
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '.' && !prev.is_some_and(|p| p.is_ascii_alphanumeric() || p == '_') => {
+                out.push_str(scope);
+            }
+            None => {}
+        }
+        out.push(c);
+        prev = Some(c);
+    }
+    out
+}
+
+// `db ...` / `dw ...`, with or without a leading dot; Some((is_dw, items)).
+fn data_directive(line: &str) -> Option<(bool, &str)> {
+    let (directive, rest) = line
+        .strip_prefix('.')
+        .unwrap_or(line)
+        .split_once(char::is_whitespace)?;
+    match directive {
+        "db" => Some((false, rest)),
+        "dw" => Some((true, rest)),
+        _ => None,
+    }
+}
 
+// `.if`, `org` and `times` are evaluated during the first pass, so they see
+// only the consts that already resolved from earlier definitions. A deferred
+// const is rejected rather than read as whatever its name happens to match.
+fn resolve_early(
+    expr: &str,
+    consts: &HashMap<String, u16>,
+    deferred: &HashSet<String>,
+) -> Result<u16, String> {
+    if let Some(name) = expr_names(expr)
+        .into_iter()
+        .find(|name| deferred.contains(*name))
+    {
+        return Err(format!(
+            "Const '{}' is not resolved yet; .if, org and times can only use consts whose values are known from earlier lines",
+            name
+        ));
+    }
+    resolve_expr(expr, consts)
+}
+
+// `times N <line>`: N is one token, resolved like `org` from the consts known
+// so far, and the line must be an instruction or a data directive.
+fn times_prefix<'a>(
+    rest: &'a str,
+    consts: &HashMap<String, u16>,
+    deferred: &HashSet<String>,
+) -> Result<(u16, &'a str), String> {
+    let (count, body) = rest
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| format!("Expected 'times N <line>', got 'times {}'", rest))?;
+    let count = resolve_early(count, consts, deferred)?;
+    let body = body.trim();
+    let directive = body.ends_with(':')
+        || body.starts_with('.')
+        || ["const ", "alias ", "org ", "times "]
+            .iter()
+            .any(|prefix| body.starts_with(prefix));
+    if directive && data_directive(body).is_none() {
+        return Err(format!(
+            "'times' repeats an instruction or data directive, not '{}'",
+            body
+        ));
+    }
+    Ok((count, body))
+}
+
+// Raw words are padded to a whole number of 4-word instruction slots so that
+// labels (which count instructions) stay aligned after the blob.
+fn raw_slots(words: &[u16]) -> usize {
+    words.len().div_ceil(4)
+}
+
+// Moves the first-pass ip past `slots` more instruction slots. The output,
+// trailing halt included, must fit in RAM (which also keeps every slot
+// addressable by u16), so the line that first runs past the end gets the
+// error; ip keeps counting after that so later lines aren't blamed as well.
+fn advance(ip: &mut usize, slots: usize) -> Result<(), String> {
+    let capacity = MEM_SIZE / 8;
+    let before = *ip;
+    *ip = ip.checked_add(slots).unwrap_or(usize::MAX);
+    if before < capacity && *ip >= capacity {
+        let size = ip.saturating_add(1).saturating_mul(8);
+        return Err(format!(
+            "Program is {} bytes, {} past the end of the {}-byte memory",
+            size,
+            size - MEM_SIZE,
+            MEM_SIZE
+        ));
+    }
+    Ok(())
+}
+
+fn assemble_full(source: &str) -> Assembled {
+    let opcodes: HashMap<&str, u16> = OPCODES.iter().copied().collect();
+
+    let mut diagnostics = vec![];
     let mut consts = HashMap::new();
+    // consts naming something not yet resolved; filled in after the first pass
+    let mut deferred = HashSet::new();
+    let mut const_defs = vec![];
+    // `alias NAME: REG` names, usable wherever a register operand is
+    let mut aliases: HashMap<String, u16> = HashMap::new();
     let mut labels = HashMap::new();
     let mut lines = vec![];
+    let mut ip: usize = 0;
+    let mut conds: Vec<CondFrame> = vec![];
+    let mut data_lines = vec![];
+    // line each label/const name was first defined on
+    let mut defined: HashMap<String, usize> = HashMap::new();
+    // most recent non-local label; `.name` labels live under it
+    let mut scope = String::new();
 
     for (i, line) in source.lines().enumerate() {
-        let line = line.split(';').next().unwrap_or("").trim();
+        let code = split_unquoted(line, ';')[0];
+        if let Some((col, ch)) = code.chars().enumerate().find(|(_, ch)| !ch.is_ascii()) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line: i + 1,
+                message: format!(
+                    "Non-ASCII character '{}' (U+{:04X}) at column {}",
+                    ch,
+                    ch as u32,
+                    col + 1
+                ),
+            });
+            continue;
+        }
+
+        let line = code.trim();
         if line.is_empty() {
             continue;
         }
 
+        let active = conds.iter().all(|c| c.taken != c.in_else);
+        if let Some(expr) = line.strip_prefix(".if ") {
+            let taken = active
+                && match resolve_early(expr, &consts, &deferred) {
+                    Ok(value) => value != 0,
+                    Err(message) => {
+                        diagnostics.push(Diagnostic {
+                            severity: Severity::Error,
+                            line: i + 1,
+                            message,
+                        });
+                        false
+                    }
+                };
+            conds.push(CondFrame {
+                line: i + 1,
+                taken,
+                in_else: false,
+            });
+            continue;
+        }
+        if line == ".else" || line == ".endif" {
+            let matched = match conds.last_mut() {
+                Some(frame) if line == ".else" && !frame.in_else => {
+                    frame.in_else = true;
+                    true
+                }
+                Some(_) if line == ".endif" => conds.pop().is_some(),
+                _ => false,
+            };
+            if !matched {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message: format!("'{}' without matching '.if'", line),
+                });
+            }
+            continue;
+        }
+        if !active {
+            continue;
+        }
+
+        let (repeat, line) = match line.strip_prefix("times ") {
+            None => (1, line),
+            Some(rest) => match times_prefix(rest, &consts, &deferred) {
+                Ok(parsed) => parsed,
+                Err(message) => {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        line: i + 1,
+                        message,
+                    });
+                    continue;
+                }
+            },
+        };
+
         if let Some(rest) = line.strip_prefix("const ") {
             if let Some((key, val)) = rest.split_once(':') {
                 let name = key.trim().to_string();
-                let value = resolve_expr(val.trim(), &consts);
-                consts.insert(name, value);
+                if let Some(message) = redefinition(&mut defined, &name, i + 1) {
+                    diagnostics.push(Diagnostic {
+                        severity: Severity::Error,
+                        line: i + 1,
+                        message,
+                    });
+                    continue;
+                }
+                // `.if`, `org` and `times` need values during this pass, so a
+                // const resolves now only if every name it uses already has;
+                // anything else (`B` in `const A: B + 1`) could be misread as
+                // a register, so it waits until all symbols are known
+                let known = expr_names(val)
+                    .into_iter()
+                    .all(|n| n.starts_with(|c: char| c.is_ascii_digit()) || consts.contains_key(n));
+                match resolve_expr(val.trim(), &consts) {
+                    Ok(value) if known => {
+                        consts.insert(name.clone(), value);
+                    }
+                    _ => {
+                        deferred.insert(name.clone());
+                    }
+                }
+                const_defs.push((name, val.trim().to_string(), i + 1));
                 continue;
             }
+        } else if let Some(rest) = line.strip_prefix("alias ") {
+            let result = match rest.split_once(':') {
+                None => Err(format!("Expected 'alias NAME: REGISTER', got '{}'", line)),
+                Some((key, target)) => {
+                    let (name, target) = (key.trim(), target.trim());
+                    if reg_index(name).is_some() {
+                        Err(format!("Alias '{}' collides with a register name", name))
+                    } else {
+                        match aliases.get(target).copied().or_else(|| reg_index(target)) {
+                            None => Err(format!("Alias target '{}' is not a register", target)),
+                            Some(reg) => match redefinition(&mut defined, name, i + 1) {
+                                Some(message) => Err(message),
+                                None => Ok((name.to_string(), reg)),
+                            },
+                        }
+                    }
+                }
+            };
+            match result {
+                Ok((name, reg)) => {
+                    aliases.insert(name, reg);
+                }
+                Err(message) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                }),
+            }
         } else if line.ends_with(':') {
             let label = line
                 .trim_end_matches(':')
@@ -152,169 +1156,725 @@ pub fn assemble(source: &str) -> Vec<u16> {
                 .strip_prefix("label ")
                 .unwrap_or_else(|| line.trim_end_matches(':').trim())
                 .to_string();
-            labels.insert(label, lines.len() as u16);
+            let label = if label.starts_with('.') {
+                format!("{}{}", scope, label)
+            } else {
+                scope = label.clone();
+                label
+            };
+            match redefinition(&mut defined, &label, i + 1) {
+                Some(message) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                }),
+                None => {
+                    labels.insert(label, ip as u16);
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix(".incbin ") {
+            match parse_incbin(rest).and_then(|words| {
+                advance(&mut ip, raw_slots(&words))?;
+                Ok(words)
+            }) {
+                Ok(words) => lines.push((i + 1, Line::Raw(words))),
+                Err(message) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                }),
+            }
+        } else if let Some(rest) = line.strip_prefix(".org ").or(line.strip_prefix("org ")) {
+            // pads with halts up to instruction slot N so later labels land there
+            match resolve_early(rest, &consts, &deferred) {
+                Ok(target) if (target as usize) < ip => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message: format!(
+                        "org {} moves backwards (already at instruction {})",
+                        target, ip
+                    ),
+                }),
+                Ok(target) => {
+                    let padding = target as usize - ip;
+                    if let Err(message) = advance(&mut ip, padding) {
+                        diagnostics.push(Diagnostic {
+                            severity: Severity::Error,
+                            line: i + 1,
+                            message,
+                        });
+                    } else if padding > 0 {
+                        let halt = opcodes["halt"] - 1;
+                        lines.push((i + 1, Line::Raw([halt, 0, 0, 0].repeat(padding))));
+                    }
+                }
+                Err(message) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                }),
+            }
+        } else if let Some((wide, rest)) = data_directive(line) {
+            // Data is padded to whole slots like .incbin, so a data label counts
+            // instruction slots too: its byte address (what save/load/loada take,
+            // with no MS/MO base added) is `label * 8`.
+            match parse_data(&qualify_locals(rest, &scope), wide).and_then(|items| {
+                // sized before expanding, so an oversized `times` fails cheaply
+                let len = data_len(&items, wide, repeat as usize)
+                    .ok_or_else(|| "Repeated data is too large".to_string())?;
+                advance(&mut ip, len.div_ceil(4))?;
+                // a repeated data line is one blob, so `times 16 db 0` is 16 bytes
+                let items: Vec<DataItem> =
+                    (0..repeat).flat_map(|_| items.iter().cloned()).collect();
+                // values may name later labels; filled in once all are known
+                Ok((items, vec![0; len]))
+            }) {
+                Ok((items, words)) => {
+                    data_lines.push((lines.len(), wide, items));
+                    lines.push((i + 1, Line::Raw(words)));
+                }
+                Err(message) => diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                }),
+            }
         } else {
-            lines.push((i + 1, line.to_string()));
+            let instruction = qualify_locals(line, &scope);
+            if let Err(message) = advance(&mut ip, repeat as usize) {
+                diagnostics.push(Diagnostic {
+                    severity: Severity::Error,
+                    line: i + 1,
+                    message,
+                });
+                continue;
+            }
+            for _ in 0..repeat {
+                lines.push((i + 1, Line::Instruction(instruction.clone())));
+            }
         }
     }
 
-    labels.extend(consts.iter().map(|(k, &v)| (k.clone(), v)));
+    for frame in conds {
+        diagnostics.push(Diagnostic {
+            severity: Severity::Error,
+            line: frame.line,
+            message: "Unterminated '.if'".to_string(),
+        });
+    }
+
+    let label_targets: HashSet<usize> = labels.values().map(|&ip| ip as usize).collect();
+
+    let values = resolve_consts(&const_defs, &labels, &mut diagnostics);
+
+    let mut symbols: Vec<Symbol> = values
+        .iter()
+        .map(|(name, &value)| Symbol {
+            name: name.clone(),
+            value,
+            kind: if labels.contains_key(name) {
+                SymbolKind::Label
+            } else {
+                SymbolKind::Const
+            },
+        })
+        .chain(aliases.iter().map(|(name, &reg)| Symbol {
+            name: name.clone(),
+            value: reg,
+            kind: SymbolKind::Alias,
+        }))
+        .collect();
+    symbols.sort_by(|x, y| x.name.cmp(&y.name));
+
+    let labels = values;
+
+    for (idx, wide, items) in data_lines {
+        match resolve_data(&items, wide, &labels) {
+            Ok(words) => lines[idx].1 = Line::Raw(words),
+            Err(message) => diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line: lines[idx].0,
+                message,
+            }),
+        }
+    }
 
     let mut result = vec![];
+    let mut source_map = vec![];
+    let mut dead_after: Option<String> = None;
+    let mut ip: usize = 0;
 
     for (lineno, line) in lines {
-        let parts: Vec<&str> = line.split_whitespace().collect();
-        if parts.is_empty() {
-            continue;
+        let line = match line {
+            Line::Instruction(line) => line,
+            Line::Raw(words) => {
+                source_map.push((lineno, (result.len() / 4) as u16));
+                let slots = raw_slots(&words);
+                result.extend_from_slice(&words);
+                result.resize(result.len().next_multiple_of(4), 0);
+                ip += slots;
+                dead_after = None;
+                continue;
+            }
+        };
+        let (mnemonic, operands) = line
+            .split_once(|c: char| c.is_ascii_whitespace())
+            .unwrap_or((&line, ""));
+
+        if label_targets.contains(&ip) {
+            dead_after = None;
+        }
+        ip += 1;
+        if let Some(prev) = dead_after.take() {
+            // Only the first instruction of an unreachable run is reported.
+            diagnostics.push(Diagnostic {
+                severity: Severity::Warning,
+                line: lineno,
+                message: format!("Unreachable code after '{}'", prev),
+            });
         }
 
-        let name = parts[0];
-        let opcode_num = *opcodes
-            .get(name)
-            .unwrap_or_else(|| panic!("Unknown instruction '{}' on line {}", name, lineno));
+        let name = mnemonic.to_ascii_lowercase();
+        let Some(&opcode_num) = opcodes.get(name.as_str()) else {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line: lineno,
+                message: format!("Unknown instruction '{}'", mnemonic),
+            });
+            continue;
+        };
         let opcode = opcode_num - 1;
 
-        let joined = parts[1..].join("");
-        let args: Vec<String> = joined
-            .split(',')
+        if name == "jmp" || name == "ret" {
+            dead_after = Some(name.clone());
+        }
+
+        // split the operands as written; each one is trimmed but keeps its
+        // inner spacing, so `' '` and `A + 1` survive intact
+        let args: Vec<String> = split_unquoted(operands, ',')
+            .into_iter()
             .map(|s| s.trim().to_string())
             .filter(|s| !s.is_empty())
             .collect();
 
-        let (mut a, mut b, mut c, mut f) = (0, 0, 0, 0);
-
-        match name {
-            "mov" => {
-                assert_eq!(args.len(), 2);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                let (bv, _) = resolve_operand(&args[1], &labels);
-                a = av;
-                b = bv;
-                if ai {
-                    f |= 1;
-                }
-            }
-            "add" | "sub" | "and" | "or" | "xor" | "shl" | "shr" => {
-                assert_eq!(args.len(), 3);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                let (bv, bi) = resolve_operand(&args[1], &labels);
-                let (cv, _) = resolve_operand(&args[2], &labels);
-                a = av;
-                b = bv;
-                c = cv;
-                if ai {
-                    f |= 1;
-                }
-                if bi {
-                    f |= 2;
-                }
-            }
-            "mul" => {
-                assert_eq!(args.len(), 2);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                let (bv, bi) = resolve_operand(&args[1], &labels);
-                a = av;
-                b = bv;
-                if ai {
-                    f |= 1;
-                }
-                if bi {
-                    f |= 2;
-                }
-            }
-            "not" => {
-                assert_eq!(args.len(), 2);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                let (cv, _) = resolve_operand(&args[1], &labels);
-                a = av;
-                c = cv;
-                if ai {
-                    f |= 1;
-                }
-            }
-            "jmp" => {
-                assert_eq!(args.len(), 1);
-                let (cv, ci) = resolve_operand(&args[0], &labels);
-                c = cv;
-                if ci {
-                    f |= 4;
-                }
-            }
-            "jml" | "jmle" | "jmb" | "jmbe" | "jme" | "jmne" => {
-                assert_eq!(args.len(), 3);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                let (bv, bi) = resolve_operand(&args[1], &labels);
-                let (cv, ci) = resolve_operand(&args[2], &labels);
-                a = av;
-                b = bv;
-                c = cv;
-                if ai {
-                    f |= 1;
-                }
-                if bi {
-                    f |= 2;
-                }
-                if ci {
-                    f |= 4;
-                }
-            }
-            "save" => {
-                // MODIFIED: save now takes 2 parameters
-                // save(dest_addr_ptr, src_value)
-                // a = src_value (what to store)
-                // b = dest_addr_ptr (where to store it)
-                assert_eq!(args.len(), 2);
-                let (av, ai) = resolve_operand(&args[0], &labels);  // dest_addr_ptr
-                let (bv, bi) = resolve_operand(&args[1], &labels);  // src_value
-                a = bv;  // store src_value in 'a' register slot
-                b = av;  // store dest_addr_ptr in 'b' register slot
-                if bi {
-                    f |= 1;  // flag for 'a' parameter (src_value)
-                }
-                if ai {
-                    f |= 2;  // flag for 'b' parameter (dest_addr_ptr)
-                }
-            }
-            "load" => {
-                // MODIFIED: load now takes 2 parameters
-                // load(dest_reg, src_addr_ptr)
-                // b = src_addr_ptr (where to read from)
-                // c = dest_reg (target register)
-                assert_eq!(args.len(), 2);
-                let (bv, bi) = resolve_operand(&args[0], &labels);  // dest_reg
-                let (cv, ci) = resolve_operand(&args[1], &labels);  // src_addr_ptr
-                b = bv;  // store dest_reg in 'b' register slot
-                c = cv;  // store src_addr_ptr in 'c' register slot
-                if bi {
-                    f |= 2;  // flag for 'b' parameter (dest_reg)
-                }
-                if ci {
-                    f |= 4;  // flag for 'c' parameter (src_addr_ptr)
-                }
-            }
-            "push" => {
-                assert_eq!(args.len(), 1);
-                let (av, ai) = resolve_operand(&args[0], &labels);
-                a = av;
-                if ai {
-                    f |= 1;
-                }
-            }
-            "pop" => {
-                assert_eq!(args.len(), 1);
-                let (av, _) = resolve_operand(&args[0], &labels);
-                a = av;
+        let mut warnings = vec![];
+        let encoded = assemble_instruction(&name, &args, &labels, &aliases, &mut warnings);
+        diagnostics.extend(warnings.into_iter().map(|message| Diagnostic {
+            severity: Severity::Warning,
+            line: lineno,
+            message,
+        }));
+
+        match encoded {
+            Ok([f, a, b, c]) => {
+                source_map.push((lineno, (result.len() / 4) as u16));
+                let header = (f << 13) | opcode;
+                result.extend_from_slice(&[header, a, b, c]);
             }
-            "halt" => continue,
-            _ => panic!("Unknown instruction '{}' on line {}", name, lineno),
+            Err(message) => diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                line: lineno,
+                message,
+            }),
         }
-
-        let header = (f << 13) | opcode;
-        result.extend_from_slice(&[header, a, b, c]);
     }
 
     let halt_opcode = (opcodes["halt"] - 1) & 0x1FFF;
     result.extend_from_slice(&[halt_opcode, 0, 0, 0]);
 
-    result
+    Assembled {
+        words: result,
+        diagnostics,
+        symbols,
+        source_map,
+    }
+}
+
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    assemble_full(source).diagnostics
+}
+
+pub fn symbols(source: &str) -> Vec<Symbol> {
+    assemble_full(source).symbols
+}
+
+pub fn source_map(source: &str) -> SourceMap {
+    assemble_full(source).source_map
+}
+
+pub fn assemble(source: &str) -> Result<Vec<u16>, AssembleError> {
+    let assembled = assemble_full(source);
+
+    match first_error(&assembled) {
+        Some(err) => Err(AssembleError {
+            line: err.line,
+            message: err.message,
+        }),
+        None => Ok(assembled.words),
+    }
+}
+
+// Code plus its source map from a single assembly, for callers that keep both.
+pub fn assemble_with_source_map(source: &str) -> Result<(Vec<u16>, SourceMap), AssembleError> {
+    let assembled = assemble_full(source);
+
+    match first_error(&assembled) {
+        Some(err) => Err(AssembleError {
+            line: err.line,
+            message: err.message,
+        }),
+        None => Ok((assembled.words, assembled.source_map)),
+    }
+}
+
+// Code plus the merged label + const map. On error the code is empty, but the
+// symbols that did resolve are still returned for the editor.
+pub fn assemble_with_symbols(source: &str) -> (Vec<u16>, HashMap<String, u16>) {
+    let assembled = assemble_full(source);
+    let symbols = assembled
+        .symbols
+        .iter()
+        .filter(|symbol| symbol.kind != SymbolKind::Alias)
+        .map(|symbol| (symbol.name.clone(), symbol.value))
+        .collect();
+    match first_error(&assembled) {
+        Some(_) => (vec![], symbols),
+        None => (assembled.words, symbols),
+    }
+}
+
+// Code plus a listing with one row per source line: instruction address, the
+// four words of each slot it emitted (data lines get one row per slot), then
+// the source text. On error the code is empty and the listing is the error.
+pub fn assemble_with_listing(source: &str) -> (Vec<u16>, String) {
+    let assembled = assemble_full(source);
+    if let Some(err) = first_error(&assembled) {
+        return (
+            vec![],
+            format!("error: {} on line {}", err.message, err.line),
+        );
+    }
+
+    let words = &assembled.words;
+    // every line's words run up to the next line's; the rest is the final halt
+    let mut spans: HashMap<usize, (usize, usize)> = HashMap::new();
+    let starts: Vec<usize> = assembled
+        .source_map
+        .iter()
+        .map(|&(_, ip)| ip as usize * 4)
+        .chain([words.len() - 4])
+        .collect();
+    for (i, &(line, _)) in assembled.source_map.iter().enumerate() {
+        // a `times` line maps to several consecutive entries
+        spans.entry(line).or_insert((starts[i], starts[i + 1])).1 = starts[i + 1];
+    }
+
+    let row = |start: usize| {
+        let slot = &words[start..start + 4];
+        format!(
+            "{:04X}  {:04X} {:04X} {:04X} {:04X}",
+            start / 4,
+            slot[0],
+            slot[1],
+            slot[2],
+            slot[3]
+        )
+    };
+    let mut rows = vec![];
+    for (i, text) in source.lines().enumerate() {
+        let text = text.trim_end();
+        match spans.get(&(i + 1)) {
+            Some(&(start, end)) => {
+                rows.push(format!("{}  {}", row(start), text));
+                rows.extend((start + 4..end).step_by(4).map(row));
+            }
+            None => rows.push(format!("{:26}{}", "", text)),
+        }
+    }
+    rows.push(row(words.len() - 4));
+
+    let listing = rows
+        .iter()
+        .map(|row| row.trim_end())
+        .collect::<Vec<_>>()
+        .join("\n");
+    (assembled.words, listing)
+}
+
+fn first_error(assembled: &Assembled) -> Option<Diagnostic> {
+    assembled
+        .diagnostics
+        .iter()
+        .find(|d| d.severity == Severity::Error)
+        .cloned()
+}
+
+fn instruction_at(words: &[u16], ip: usize) -> [u16; 4] {
+    let mut slot = [0u16; 4];
+    for (i, word) in words.iter().skip(ip * 4).take(4).enumerate() {
+        slot[i] = *word;
+    }
+    slot
+}
+
+pub fn verify_roundtrip(source: &str) -> Result<(), RoundtripError> {
+    let original = assemble_full(source);
+    if let Some(err) = first_error(&original) {
+        return Err(RoundtripError::Assemble(err));
+    }
+
+    // the last slot is the halt assemble appends; reassembling appends it again
+    let listing: Vec<String> = (0..original.words.len().div_ceil(4) - 1)
+        .map(|ip| disassemble_instruction(instruction_at(&original.words, ip), None))
+        .collect();
+    let reassembled = assemble_full(&listing.join("\n"));
+    if let Some(err) = first_error(&reassembled) {
+        return Err(RoundtripError::Reassemble(err));
+    }
+
+    let slots = original
+        .words
+        .len()
+        .max(reassembled.words.len())
+        .div_ceil(4);
+    for ip in 0..slots {
+        let original = instruction_at(&original.words, ip);
+        let reassembled = instruction_at(&reassembled.words, ip);
+        if original != reassembled {
+            return Err(RoundtripError::Mismatch {
+                ip,
+                original,
+                reassembled,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{Emulator, REG_A, REG_B};
+
+    fn run(source: &str) -> Emulator {
+        let mut emu = Emulator::new();
+        emu.load_program(&assemble(source).unwrap()).unwrap();
+        emu.run(100);
+        emu
+    }
+
+    fn count(diagnostics: &[Diagnostic], severity: Severity) -> usize {
+        diagnostics
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+
+    #[test]
+    fn check_reports_errors_and_warnings() {
+        // unreachable code after jmp, an unknown instruction, an offset decoded as negative
+        let diagnostics = check("jmp 0\nmov 1, A\nfoo A\nmov A+9, B\n");
+        assert_eq!(count(&diagnostics, Severity::Error), 1);
+        assert_eq!(count(&diagnostics, Severity::Warning), 2);
+        let error = diagnostics
+            .iter()
+            .find(|d| d.severity == Severity::Error)
+            .unwrap();
+        assert_eq!(error.line, 3);
+        assert!(check("start:\nmov 1, A\njmp start\n").is_empty());
+        // warnings alone don't fail assembly
+        assert!(assemble("mov A+9, B\n").is_ok());
+    }
+
+    #[test]
+    fn hex_literals() {
+        let no_symbols = HashMap::new();
+        assert_eq!(resolve_expr("0xFF", &no_symbols), Ok(255));
+        assert_eq!(resolve_expr("0Xab", &no_symbols), Ok(0xAB));
+        assert_eq!(resolve_expr("0x1000", &no_symbols), Ok(4096));
+        let err = assemble("mov 0x10000, A\n").unwrap_err();
+        assert!(
+            err.message
+                .contains("Literal '0x10000' does not fit in 16 bits")
+        );
+
+        // immediates are whole words, so the top nibble survives a round trip
+        let words = assemble("mov 0xF00F, A\n").unwrap();
+        assert_eq!(words[1], 0xF00F);
+        assert_eq!(run("mov 0xF00F, A\n").get_register(REG_A), 0xF00F);
+    }
+
+    #[test]
+    fn incbin_packs_hex_bytes() {
+        let words = assemble(".incbin 3412 78\nmov 1, A\n").unwrap();
+        assert_eq!(&words[..4], &[0x1234, 0x0078, 0, 0]);
+        // the data fills one slot, so the mov lands at ip 1
+        assert_eq!(source_map(".incbin 3412 78\nmov 1, A\n")[1], (2, 1));
+
+        let err = assemble("mov 1, A\n.incbin 12 3G\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(
+            err.message.contains("Invalid .incbin data '12 3G'"),
+            "{}",
+            err
+        );
+        assert!(assemble(".incbin 123\n").is_err());
+    }
+
+    #[test]
+    fn nested_conditionals() {
+        let source = "const DEBUG: 1\nconst LEVEL: 0\nmov 9, A\n\
+            .if DEBUG\n.if LEVEL\nmov 1, A\n.else\nmov 2, A\n.endif\n.else\nmov 3, A\n.endif\n\
+            .if LEVEL\n.if DEBUG\nmov 4, B\n.else\nmov 5, B\n.endif\n.endif\n";
+        let emu = run(source);
+        assert_eq!(emu.get_register(REG_A), 2);
+        // the inner .else of a skipped outer block stays skipped
+        assert_eq!(emu.get_register(REG_B), 0);
+
+        let err = assemble(".if 1\nnop\n.endif\n.endif\n").unwrap_err();
+        assert_eq!(err.line, 4);
+        assert!(err.message.contains("'.endif' without matching '.if'"));
+        let err = assemble(".if 1\n.if 0\n.endif\n").unwrap_err();
+        assert!(err.message.contains("Unterminated '.if'"));
+    }
+
+    #[test]
+    fn non_ascii_source() {
+        // comments may hold anything
+        assert_eq!(run("mov 1, A ; café\n").get_register(REG_A), 1);
+
+        let err = assemble("nop\nmov 1, Ä\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(
+            err.message
+                .contains("Non-ASCII character 'Ä' (U+00C4) at column 8"),
+            "{}",
+            err
+        );
+        assert_eq!(assemble("db \"é\"\n").unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn char_literals() {
+        let no_symbols = HashMap::new();
+        assert_eq!(resolve_expr("'A'", &no_symbols), Ok(65));
+        assert_eq!(resolve_expr("'\\n'", &no_symbols), Ok(10));
+        assert_eq!(resolve_expr("'\\''", &no_symbols), Ok(39));
+        assert_eq!(resolve_expr("'\\\\'", &no_symbols), Ok(92));
+        assert_eq!(resolve_expr("'a' + 1", &no_symbols), Ok(98));
+        assert_eq!(
+            run("mov ';', A ; a quoted semicolon\n").get_register(REG_A),
+            59
+        );
+
+        assert!(
+            assemble("mov 'ab', A\n")
+                .unwrap_err()
+                .message
+                .contains("Invalid char literal")
+        );
+        assert!(assemble("mov '\\q', A\n").is_err());
+        let err = assemble("nop\ndw 'x\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("Unterminated char literal"), "{}", err);
+    }
+
+    #[test]
+    fn org_places_labels() {
+        let symbols = symbols("nop\norg 4\nhandler:\nnop\n");
+        assert_eq!(symbols[0].name, "handler");
+        assert_eq!(symbols[0].value, 4);
+        // the gap is filled with halts
+        let words = assemble("nop\norg 4\nhandler:\nnop\n").unwrap();
+        assert_eq!(words[4] & 0x1FFF, words[20] & 0x1FFF);
+        assert_eq!(words.len(), 6 * 4);
+
+        let err = assemble("org 4\nnop\norg 2\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(err.message.contains("org 2 moves backwards"), "{}", err);
+
+        let err = assemble("org 65535\nnop\nnop\n").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(err.message.contains("past the end"), "{}", err);
+        // 8191 slots + nop + halt = 8193 slots = 65544 bytes
+        let err = assemble("org 8191\nnop\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("65544 bytes, 8 past"), "{}", err);
+    }
+
+    #[test]
+    fn negative_literals() {
+        let no_symbols = HashMap::new();
+        assert_eq!(resolve_expr("-1", &no_symbols), Ok(0xFFFF));
+        assert_eq!(resolve_expr("2 - -3", &no_symbols), Ok(5));
+        assert_eq!(symbols("const NEG: -10\n")[0].value, (-10i16) as u16);
+        let words = assemble("const NEG: -10\ndw -1, NEG, -32768\n").unwrap();
+        assert_eq!(&words[..3], &[0xFFFF, 0xFFF6, 0x8000]);
+
+        let emu = run("const NEG: -10\nmov -1, A\nadd NEG, 0, B\n");
+        assert_eq!(emu.get_register(REG_A), 0xFFFF);
+        assert_eq!(emu.get_register(REG_B), 0xFFF6);
+        // register offsets keep their own encoding
+        assert_eq!(run("mov 5, A\nmov A-1, B\n").get_register(REG_B), 4);
+        assert!(assemble("mov -32769, A\n").is_err());
+    }
+
+    #[test]
+    fn source_map_comes_with_the_words() {
+        let source = "; header\nstart:\nmov 1, A\n\njmp start\n";
+        let (words, map) = assemble_with_source_map(source).unwrap();
+        assert_eq!(words, assemble(source).unwrap());
+        assert_eq!(map, [(3, 0), (5, 1)]);
+        assert_eq!(map, source_map(source));
+        let err = assemble_with_source_map("nop\nfoo\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn local_labels_are_scoped_to_the_last_global() {
+        let source = "first:\nnop\n.end:\njmp .end\nsecond:\nnop\n.end:\njmp .end\n";
+        let symbols = symbols(source);
+        let value = |name: &str| symbols.iter().find(|s| s.name == name).unwrap().value;
+        assert_eq!(value("first.end"), 1);
+        assert_eq!(value("second.end"), 3);
+        // each jmp targets its own scope's .end
+        let words = assemble(source).unwrap();
+        assert_eq!((words[7], words[15]), (1, 3));
+
+        // first's .end isn't visible from second
+        let err = assemble("first:\n.end:\nnop\nsecond:\njmp .end\n").unwrap_err();
+        assert_eq!(err.line, 5);
+        assert!(err.message.contains("second.end"), "{}", err);
+    }
+
+    #[test]
+    fn duplicate_definitions() {
+        // the same local name under two globals is fine
+        assert!(assemble("a_fn:\n.done:\nnop\nb_fn:\n.done:\nnop\n").is_ok());
+
+        let err = assemble("start:\nnop\nstart:\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        assert_eq!(
+            err.message,
+            "'start' is already defined on line 1 (redefined on line 3)"
+        );
+        let err = assemble("const SIZE: 4\nnop\nconst SIZE: 5\n").unwrap_err();
+        assert_eq!(err.line, 3);
+        // labels and consts share one namespace
+        let err = assemble("const SIZE: 4\nSIZE:\nnop\n").unwrap_err();
+        assert!(err.message.contains("'SIZE' is already defined on line 1"));
+    }
+
+    #[test]
+    fn early_consts_wait_for_forward_references() {
+        // FIRST refers to a const defined after it
+        let forward = "const FIRST: SECOND + 1\nconst SECOND: 5\n";
+        let syms = symbols(forward);
+        assert_eq!(syms.iter().find(|s| s.name == "FIRST").unwrap().value, 6);
+        let emu = run(&format!("{}mov FIRST, A\n", forward));
+        assert_eq!(emu.get_register(REG_A), 6);
+
+        // .if, org and times run in the first pass, before FIRST is known
+        let err = assemble(&format!("{}.if FIRST\nnop\n.endif\n", forward)).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(
+            err.message.contains("'FIRST' is not resolved yet"),
+            "{}",
+            err
+        );
+        assert!(assemble("const COUNT: LATER\ntimes COUNT nop\nconst LATER: 2\n").is_err());
+        assert!(assemble("const START: LATER\norg START\nconst LATER: 2\n").is_err());
+
+        // defined in order, the same consts work there
+        let emu = run(
+            "const SECOND: 5\nconst FIRST: SECOND + 1\n.if FIRST - 6\nmov 1, A\n.else\nmov 2, A\n.endif\n",
+        );
+        assert_eq!(emu.get_register(REG_A), 2);
+        assert_eq!(
+            assemble("const COUNT: 2\ntimes COUNT nop\n").unwrap().len(),
+            12
+        );
+
+        // a const may name a label defined later
+        let words = assemble("const END: done * 8\nmov END, A\ndone:\nmov 1, B\n").unwrap();
+        assert_eq!(words, assemble("mov 8, A\nmov 1, B\n").unwrap());
+        let err = assemble("const FIRST: SECOND + 1\nconst SECOND: FIRST + 1\n").unwrap_err();
+        assert!(
+            err.message.starts_with("Cannot resolve const 'FIRST'"),
+            "{}",
+            err
+        );
+
+        // a pending const named like a register isn't read as the register
+        let syms = symbols("const FIRST: B + 1\nconst B: 5\n");
+        assert_eq!(syms.iter().find(|s| s.name == "FIRST").unwrap().value, 6);
+    }
+
+    #[test]
+    fn symbols_report_kinds() {
+        let source = "const K: 3\nalias COUNTER: B\nalias TMP: COUNTER\nstart:\nmov K, COUNTER\n";
+        let kinds: Vec<(String, u16, SymbolKind)> = symbols(source)
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.value, symbol.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                ("COUNTER".to_string(), 1, SymbolKind::Alias),
+                ("K".to_string(), 3, SymbolKind::Const),
+                ("TMP".to_string(), 1, SymbolKind::Alias),
+                ("start".to_string(), 0, SymbolKind::Label),
+            ]
+        );
+        // the value map stays labels and consts, so aliases never name a jump target
+        let (_, map) = assemble_with_symbols(source);
+        assert!(!map.contains_key("COUNTER"));
+    }
+
+    #[test]
+    fn times_past_last_instruction_is_an_error() {
+        let err = assemble("times 4000 nop\ntimes 40000 nop\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("past the end"), "{}", err);
+        let err = assemble("nop\ntimes 65535 dw 1, 2, 3, 4, 5\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        // db packs across repeats: 3 * 3 bytes is 5 words
+        let words = assemble("times 3 db 1, 2, 3\n").unwrap();
+        assert_eq!(&words[..5], &[0x0201, 0x0103, 0x0302, 0x0201, 0x0003]);
+    }
+
+    #[test]
+    fn oversized_program_is_an_error() {
+        // 8190 slots + 2 nops + halt = 8193 slots = 65544 bytes
+        let err = assemble("nop\n.org 8190\nnop\nnop\n").unwrap_err();
+        assert_eq!(err.line, 4);
+        assert!(err.message.contains("8 past"), "{}", err);
+        // only the first line past the end is reported
+        let diagnostics = check("nop\n.org 8190\nnop\nnop\nnop\n");
+        assert_eq!(count(&diagnostics, Severity::Error), 1);
+        // exactly full still assembles
+        assert_eq!(assemble("nop\n.org 8190\nnop\n").unwrap().len(), 32768);
+        let err = assemble("nop\ntimes 40000 dw 0\n").unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn times_repeats_instructions_and_data() {
+        let words = assemble("times 3 nop\nafter:\njmp after\n").unwrap();
+        let nop = &assemble("nop\n").unwrap()[..4];
+        assert_eq!(words.len(), 5 * 4);
+        assert!(words[..12].chunks(4).all(|slot| slot == nop));
+        assert_eq!(words[15], 3);
+
+        // 10 bytes = 5 words = 2 slots
+        let (_, map) = assemble_with_symbols("const N: 2\ntimes N nop\ntimes 10 db 7\nend:\n");
+        assert_eq!(map["end"], 4);
+        assert_eq!(
+            assemble("times 3 dw 0xAB\n").unwrap()[..4],
+            [0xAB, 0xAB, 0xAB, 0]
+        );
+        assert!(assemble("times 2 foo:\n").is_err());
+        assert!(assemble("times 2\n").is_err());
+    }
 }